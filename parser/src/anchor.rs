@@ -0,0 +1,270 @@
+use crate::arch::{markAsSingleChild, ArcH, Span, Vertex};
+use std::collections::BTreeMap;
+
+// Anchors and references: `&name` before a vertex names the subtree that starts there
+// (the rest of its chain and its children), and `*name` pastes that subtree elsewhere:
+//
+//   [UI::App]
+//   &form UI::TaskForm ><submits> Logic::saveTask
+//     - Logic::validate
+//
+//   [UI::Admin]
+//   ><renders> *form
+//
+// A reference may stand for a whole statement, a `- ` child or the end of a chain. Followed
+// by a fish, it stands for the anchored vertex alone. Anchors may be defined in any
+// section, before or after they are used. References are resolved on the parsed ArcH
+// trees, so the pasted vertices keep their spans at the anchor and get the reference's
+// span as `call_site`.
+
+#[derive(Debug, Clone)]
+pub struct Anchor {
+    pub body: ArcH,
+    // the `&name`
+    pub span: Option<Span>,
+}
+
+pub type Anchors = BTreeMap<String, Anchor>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnchorError {
+    pub message: String,
+    pub span: Option<Span>,
+}
+
+fn is_name(s: &str) -> bool {
+    !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+}
+
+// "&name rest of the line" -> ("&name", "rest of the line")
+pub fn split(line: &str) -> Option<(&str, &str)> {
+    let rest = line.strip_prefix('&')?;
+    let end = rest.find(char::is_whitespace)?;
+    if !is_name(&rest[..end]) || rest[end..].trim().is_empty() {
+        return None;
+    }
+    Some((&line[..end + 1], rest[end..].trim_start()))
+}
+
+// `*name` used as a vertex
+fn reference_of(vertex: &Vertex) -> Option<&str> {
+    match &vertex.segments[..] {
+        [segment] if vertex.kind.is_path() => segment.strip_prefix('*').filter(|n| is_name(n)),
+        _ => None,
+    }
+}
+
+// the vertex a subtree starts with
+fn head(arch: &ArcH) -> Option<&Vertex> {
+    match arch {
+        ArcH::ArcH { vertex, .. } | ArcH::Single { vertex, .. } => Some(vertex),
+        ArcH::ArcHWithNewLines { prefix, .. } => head(prefix),
+        ArcH::EvalStatement { .. } => None,
+    }
+}
+
+struct Resolver<'a> {
+    anchors: &'a Anchors,
+    // anchors being pasted, to catch cycles
+    stack: Vec<String>,
+}
+
+pub fn resolve(arches: Vec<ArcH>, anchors: &Anchors) -> Result<Vec<ArcH>, AnchorError> {
+    let mut resolver = Resolver {
+        anchors,
+        stack: vec![],
+    };
+    arches.into_iter().map(|a| resolver.node(a)).collect()
+}
+
+impl Resolver<'_> {
+    fn node(&mut self, arch: ArcH) -> Result<ArcH, AnchorError> {
+        match arch {
+            ArcH::Single {
+                vertex,
+                is_single_child,
+            } if reference_of(&vertex).is_some() => {
+                let body = self.paste(&vertex)?;
+                Ok(if is_single_child {
+                    markAsSingleChild(body)
+                } else {
+                    body
+                })
+            }
+            ArcH::ArcH {
+                vertex,
+                fish,
+                next,
+                is_single_child,
+            } => Ok(ArcH::ArcH {
+                vertex: match reference_of(&vertex) {
+                    Some(_) => self.vertex(&vertex)?,
+                    None => vertex,
+                },
+                fish,
+                next: Box::new(self.node(*next)?),
+                is_single_child,
+            }),
+            ArcH::ArcHWithNewLines {
+                prefix,
+                children,
+                is_single_child,
+            } => Ok(ArcH::ArcHWithNewLines {
+                prefix: Box::new(self.node(*prefix)?),
+                children: children
+                    .into_iter()
+                    .map(|c| self.node(c))
+                    .collect::<Result<_, _>>()?,
+                is_single_child,
+            }),
+            other => Ok(other),
+        }
+    }
+
+    // the anchored subtree, with its own references resolved
+    fn body(&mut self, reference: &Vertex) -> Result<ArcH, AnchorError> {
+        let error = |message: String| AnchorError {
+            message,
+            span: reference.span,
+        };
+        let name = reference_of(reference).expect("body is only called on references");
+        let anchor = self
+            .anchors
+            .get(name)
+            .ok_or_else(|| error(format!("undefined anchor `*{}`", name)))?;
+        if let Some(start) = self.stack.iter().position(|n| n == name) {
+            let cycle: Vec<String> = self.stack[start..]
+                .iter()
+                .chain([&name.to_string()])
+                .map(|n| format!("*{}", n))
+                .collect();
+            return Err(error(format!(
+                "anchor `&{}` contains itself: {}",
+                name,
+                cycle.join(" -> ")
+            )));
+        }
+        self.stack.push(name.to_string());
+        let body = self.node(anchor.body.clone())?;
+        self.stack.pop();
+        Ok(body)
+    }
+
+    fn paste(&mut self, reference: &Vertex) -> Result<ArcH, AnchorError> {
+        let mut body = self.body(reference)?;
+        // the outermost reference is the one in the section, so it wins over nested ones
+        body.for_each_mut(&mut |v| v.call_site = reference.span, &mut |f| {
+            f.call_site = reference.span
+        });
+        Ok(body)
+    }
+
+    // a reference followed by a fish: just the anchored vertex
+    fn vertex(&mut self, reference: &Vertex) -> Result<Vertex, AnchorError> {
+        let body = self.body(reference)?;
+        match head(&body) {
+            Some(vertex) if !vertex.is_placeholder() => Ok(Vertex {
+                call_site: reference.span,
+                ..vertex.clone()
+            }),
+            _ => Err(AnchorError {
+                message: format!(
+                    "`{}` does not start with a single vertex, so it cannot be followed by a fish",
+                    reference.name()
+                ),
+                span: reference.span,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{error, written};
+
+    #[test]
+    fn split_takes_the_name_off() {
+        assert_eq!(
+            split("&form UI::Form ><x> B"),
+            Some(("&form", "UI::Form ><x> B"))
+        );
+        assert_eq!(split("&a-b_1   B"), Some(("&a-b_1", "B")));
+        // a name needs something after it
+        assert_eq!(split("&form"), None);
+        assert_eq!(split("&form   "), None);
+        assert_eq!(split("&fo.rm B"), None);
+        assert_eq!(split("& B"), None);
+    }
+
+    #[test]
+    fn references_paste_the_subtree() {
+        assert_eq!(
+            written("[A]\n&f X ><y> Z\n  - W\n\n[B]\n><has> *f\n"),
+            ["A ><> X ><y> Z ><> W", "B ><has> X ><y> Z ><> W",]
+        );
+        // defined after it is used, in a later section
+        assert_eq!(
+            written("[B]\n><has> *late\n\n[C]\n&late X ><y> Z\n"),
+            ["B ><has> X ><y> Z", "C ><> X ><y> Z"]
+        );
+    }
+
+    #[test]
+    fn a_reference_followed_by_a_fish_is_the_anchored_vertex() {
+        assert_eq!(
+            written("[A]\n&f X ><y> Z\n\n[B]\n*f ><styledBy> T\n"),
+            ["A ><> X ><y> Z", "B ><> X ><styledBy> T"]
+        );
+        assert_eq!(
+            error("[A]\n&f ><y> Z\n\n[B]\n*f ><styledBy> T\n"),
+            (
+                "`*f` does not start with a single vertex, so it cannot be followed by a fish"
+                    .to_string(),
+                Some((5, 1))
+            )
+        );
+    }
+
+    #[test]
+    fn undefined_anchors() {
+        assert_eq!(
+            error("[A]\nX ><y> *nope\n"),
+            ("undefined anchor `*nope`".to_string(), Some((2, 8)))
+        );
+    }
+
+    #[test]
+    fn anchors_defined_twice() {
+        assert_eq!(
+            error("[A]\n&f X\n&f Y\n"),
+            (
+                "anchor `&f` is already defined on line 2".to_string(),
+                Some((3, 1))
+            )
+        );
+    }
+
+    #[test]
+    fn anchors_that_contain_themselves() {
+        assert_eq!(
+            // the statement that defines `&a` is resolved first, and comes back round to `*b`
+            error("[A]\n&a X ><y> *b\n&b Y ><z> *a\n").0,
+            "anchor `&b` contains itself: *b -> *a -> *b"
+        );
+        assert_eq!(
+            error("[A]\n&a X ><y> *a\n").0,
+            "anchor `&a` contains itself: *a -> *a"
+        );
+    }
+
+    #[test]
+    fn anchors_cannot_be_defined_in_a_template() {
+        assert_eq!(
+            error("[template T(x)]\n&f ${x} ><y> Z\n").0,
+            "anchors cannot be defined in a template"
+        );
+    }
+}
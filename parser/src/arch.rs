@@ -1,17 +1,241 @@
-use serde::Serialize;
+use crate::attributes::Attributes;
+use crate::expr::Expr;
+use serde::{Deserialize, Serialize};
 use std::error::Error;
 use std::fmt;
+use std::hash::{Hash, Hasher};
 // use std::fmt::Write;
+
+// Byte range of a piece of syntax in the source text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    // Locate `part` in `source`; only works if `part` was sliced out of `source`
+    pub fn within(source: &str, part: &str) -> Option<Span> {
+        let base = source.as_ptr() as usize;
+        let start = (part.as_ptr() as usize).checked_sub(base)?;
+        if start + part.len() > source.len() {
+            return None;
+        }
+        Some(Span {
+            start,
+            end: start + part.len(),
+        })
+    }
+
+    // 1-based line and column of the start of the span
+    pub fn line_col(&self, source: &str) -> (usize, usize) {
+        let before = &source[..self.start.min(source.len())];
+        let line = before.matches('\n').count() + 1;
+        let column = before.chars().rev().take_while(|c| *c != '\n').count() + 1;
+        (line, column)
+    }
+}
+
+// What a vertex stands for. Only paths are split on `::`; the others keep their text
+// whole, as the single segment.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum VertexKind {
+    // UI::App
+    #[default]
+    Path,
+    // "any text"
+    Literal,
+    // a ``` fenced block, with an optional language tag (```sql)
+    Code,
+    // $( ... )
+    Expression,
+    // `_`, or the vertex left out before a leading fish or after a trailing one; combineArcHs
+    // puts the vertex the chain attaches to in its place
+    Placeholder,
+}
+
+impl VertexKind {
+    pub fn is_path(&self) -> bool {
+        *self == VertexKind::Path
+    }
+}
+
 // Define the Vertex type
-#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
-pub struct Vertex(pub Vec<String>);
+// (the spans are only location info: they are ignored by comparisons and not serialized)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Vertex {
+    pub segments: Vec<String>,
+    // left out of the JSON for paths, so older documents read the same
+    #[serde(default, skip_serializing_if = "VertexKind::is_path")]
+    pub kind: VertexKind,
+    // the tag of a code block, e.g. "sql"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    // UI::App{owner="ui-team"}; not part of what makes two vertices the same
+    #[serde(default, skip_serializing_if = "Attributes::is_empty")]
+    pub attributes: Attributes,
+    #[serde(skip)]
+    pub span: Option<Span>,
+    // for vertices pasted in by a template: where the template was used (span is then in the template)
+    #[serde(skip)]
+    pub call_site: Option<Span>,
+}
+
+// Which way a fish points
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum FishDirection {
+    // A ><label> B: from A to B
+    #[default]
+    Forward,
+    // A <label>< B: from B to A
+    Reverse,
+    // A <>label<> B: both ways
+    Both,
+}
+
+impl FishDirection {
+    pub fn is_forward(&self) -> bool {
+        *self == FishDirection::Forward
+    }
+}
 
 // Define the Fish type
-#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
-pub struct Fish(pub String);
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Fish {
+    pub label: String,
+    // left out of the JSON when forward; edges() turns the others into forward edges
+    #[serde(default, skip_serializing_if = "FishDirection::is_forward")]
+    pub direction: FishDirection,
+    // ><calls{timeout=5s}>; like the vertex's, not part of what makes two fish the same
+    #[serde(default, skip_serializing_if = "Attributes::is_empty")]
+    pub attributes: Attributes,
+    #[serde(skip)]
+    pub span: Option<Span>,
+    #[serde(skip)]
+    pub call_site: Option<Span>,
+}
+
+impl Vertex {
+    pub fn new(segments: Vec<String>) -> Vertex {
+        Vertex {
+            segments,
+            kind: VertexKind::Path,
+            language: None,
+            attributes: Attributes::new(),
+            span: None,
+            call_site: None,
+        }
+    }
+
+    // Placeholders keep the one empty segment they have always had in output.json
+    pub fn placeholder() -> Vertex {
+        Vertex {
+            kind: VertexKind::Placeholder,
+            ..Vertex::new(vec![String::new()])
+        }
+    }
+
+    pub fn is_placeholder(&self) -> bool {
+        self.kind == VertexKind::Placeholder
+    }
+
+    // a vertex of another kind, e.g. the text of a code block
+    pub fn with_kind(text: &str, kind: VertexKind, language: Option<String>) -> Vertex {
+        Vertex {
+            kind,
+            language,
+            ..Vertex::new(vec![text.to_string()])
+        }
+    }
+
+    // the vertex written back in lo.fish syntax, e.g. UI::App
+    pub fn name(&self) -> String {
+        let text = self.segments.join("::");
+        match self.kind {
+            VertexKind::Path => text,
+            VertexKind::Literal => format!("\"{}\"", text),
+            VertexKind::Code => format!(
+                "```{}\n{}\n```",
+                self.language.as_deref().unwrap_or(""),
+                text
+            ),
+            VertexKind::Expression => format!("$({})", text),
+            VertexKind::Placeholder => "_".to_string(),
+        }
+    }
+}
+
+impl Fish {
+    // the fish written back in lo.fish syntax, e.g. ><renders>
+    pub fn operator(&self) -> String {
+        match self.direction {
+            FishDirection::Forward => format!("><{}>", self.label),
+            FishDirection::Reverse => format!("<{}><", self.label),
+            FishDirection::Both => format!("<>{}<>", self.label),
+        }
+    }
+
+    pub fn new(label: &str) -> Fish {
+        Fish {
+            label: label.to_string(),
+            direction: FishDirection::Forward,
+            attributes: Attributes::new(),
+            span: None,
+            call_site: None,
+        }
+    }
+}
+
+impl PartialEq for Vertex {
+    fn eq(&self, other: &Self) -> bool {
+        self.segments == other.segments && self.kind == other.kind
+    }
+}
+
+impl Eq for Vertex {}
+
+impl Hash for Vertex {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.segments.hash(state);
+        self.kind.hash(state);
+    }
+}
+
+impl PartialOrd for Vertex {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Vertex {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (&self.segments, self.kind).cmp(&(&other.segments, other.kind))
+    }
+}
+
+impl PartialEq for Fish {
+    fn eq(&self, other: &Self) -> bool {
+        self.label == other.label && self.direction == other.direction
+    }
+}
+
+impl Eq for Fish {}
+
+impl Hash for Fish {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.label.hash(state);
+        self.direction.hash(state);
+    }
+}
 
 // Define the ArcH enum corresponding to the Haskell data type
-#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ArcH {
     ArcH {
         is_single_child: bool,
@@ -31,35 +255,136 @@ pub enum ArcH {
     // speical case for EVAL
     EvalStatement {
         expression: String,
+        // the expression parsed with expr::parse
+        #[serde(default)]
+        parsed: Option<Expr>,
     },
 }
 
-#[derive(Clone, serde::Serialize)]
+impl ArcH {
+    // visit every vertex and fish of the tree, e.g. to rewrite them after parsing
+    pub fn for_each_mut(
+        &mut self,
+        vertex: &mut impl FnMut(&mut Vertex),
+        fish: &mut impl FnMut(&mut Fish),
+    ) {
+        match self {
+            ArcH::ArcH {
+                vertex: v,
+                fish: f,
+                next,
+                ..
+            } => {
+                vertex(v);
+                fish(f);
+                next.for_each_mut(vertex, fish);
+            }
+            ArcH::Single { vertex: v, .. } => vertex(v),
+            ArcH::ArcHWithNewLines {
+                prefix, children, ..
+            } => {
+                prefix.for_each_mut(vertex, fish);
+                for child in children {
+                    child.for_each_mut(vertex, fish);
+                }
+            }
+            ArcH::EvalStatement { .. } => {}
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct OriginalArcHForm {
+    #[serde(with = "vf_pairs")]
     pub vf_pairs: Vec<(Vertex, Fish)>,
     pub last_point: Vertex,
     pub executable_expression: String,
+    // structured form of executable_expression, for EVAL statements
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expression: Option<Expr>,
+}
+
+// in JSON every pair is an object, { "vertex": ..., "fish": ... }, rather than a tuple
+mod vf_pairs {
+    use super::{Fish, Vertex};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize)]
+    struct VfPairRef<'a> {
+        vertex: &'a Vertex,
+        fish: &'a Fish,
+    }
+
+    #[derive(Deserialize)]
+    struct VfPair {
+        vertex: Vertex,
+        fish: Fish,
+    }
+
+    pub fn serialize<S: Serializer>(
+        pairs: &[(Vertex, Fish)],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(
+            pairs
+                .iter()
+                .map(|(vertex, fish)| VfPairRef { vertex, fish }),
+        )
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<(Vertex, Fish)>, D::Error> {
+        let pairs = Vec::<VfPair>::deserialize(deserializer)?;
+        Ok(pairs.into_iter().map(|p| (p.vertex, p.fish)).collect())
+    }
+}
+
+impl OriginalArcHForm {
+    // an EVAL statement: no vertices, only the expression
+    pub fn is_eval(&self) -> bool {
+        !self.executable_expression.is_empty()
+    }
+
+    // the (from, fish, to) triples of the path, e.g. A ><x> B ><y> C gives (A, x, B) and (B, y, C);
+    // a reverse fish gives its edge the other way round, and a bidirectional one gives both
+    pub fn edges(&self) -> Vec<(&Vertex, &Fish, &Vertex)> {
+        let mut edges = Vec::new();
+        for (i, (v, f)) in self.vf_pairs.iter().enumerate() {
+            let to = match self.vf_pairs.get(i + 1) {
+                Some((next, _)) => next,
+                None => &self.last_point,
+            };
+            match f.direction {
+                FishDirection::Forward => edges.push((v, f, to)),
+                FishDirection::Reverse => edges.push((to, f, v)),
+                FishDirection::Both => {
+                    edges.push((v, f, to));
+                    edges.push((to, f, v));
+                }
+            }
+        }
+        edges
+    }
 }
 
 // print nicely
 impl fmt::Display for Vertex {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let Vertex(v) = self;
-        write!(f, "{:?}", v)
+        write!(f, "{:?}", self.segments)
     }
 }
 
 impl fmt::Display for Fish {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let Fish(f_) = self;
-        write!(f, "{}", f_)
+        write!(f, "{}", self.label)
     }
 }
 impl fmt::Display for OriginalArcHForm {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let mut s = String::new();
         for (v, f) in self.vf_pairs.iter() {
-            s.push_str(&format!("{} ><{}> ", v, f));
+            s.push_str(&format!("{} {} ", v, f.operator()));
         }
         s.push_str(&format!("{}", self.last_point));
         write!(f, "{}", s)
@@ -71,7 +396,7 @@ impl fmt::Debug for OriginalArcHForm {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let mut s = String::new();
         for (v, f) in self.vf_pairs.iter() {
-            s.push_str(&format!("{} ><{}> ", v, f));
+            s.push_str(&format!("{} {} ", v, f.operator()));
         }
         s.push_str(&format!("{}", self.last_point));
         write!(f, "{}", s)
@@ -93,21 +418,28 @@ pub fn convertToOriginalForm(
 
             // println!("\n\nunwrapping next: {:?}\n", next);
 
+            // `next` fans out when the end of the chain has children: one form per path
             let oform = convertToOriginalForm(None, *next);
-            let first = oform.first().unwrap();
-            let mut vf_pairs = first.vf_pairs.clone();
-            vf_pairs.insert(0, pair);
-
-            let finalResult = vec![
-                (combineArcHs(
-                    prefixFromOutside,
-                    OriginalArcHForm {
-                        vf_pairs: vf_pairs,
-                        last_point: first.last_point.clone(),
-                        executable_expression: "".to_string(),
-                    },
-                )),
-            ];
+            let finalResult = oform
+                .into_iter()
+                .map(|form| {
+                    // EVAL statements among the children stay forms of their own
+                    if form.is_eval() {
+                        return form;
+                    }
+                    let mut vf_pairs = form.vf_pairs;
+                    vf_pairs.insert(0, pair.clone());
+                    combineArcHs(
+                        prefixFromOutside.clone(),
+                        OriginalArcHForm {
+                            vf_pairs: vf_pairs,
+                            last_point: form.last_point,
+                            executable_expression: "".to_string(),
+                            expression: None,
+                        },
+                    )
+                })
+                .collect();
 
             // print!("\narcH finalParsed: {:?}\n\n", finalResult);
 
@@ -123,6 +455,7 @@ pub fn convertToOriginalForm(
                     vf_pairs: vec![],
                     last_point: vertex,
                     executable_expression: "".to_string(),
+                    expression: None,
                 },
             )];
         }
@@ -131,86 +464,90 @@ pub fn convertToOriginalForm(
             children,
             is_single_child,
         } => {
-            let mut results: Vec<OriginalArcHForm> = vec![];
-            let mut results_with_tails: Vec<OriginalArcHForm> = vec![];
-            let mut prefix_ = convertToOriginalForm(prefixFromOutside, *prefix)
-                .first()
-                .unwrap()
-                .clone();
-            for child in children {
-                // println!("\n\nchild: {:?}\n", child);
-                let grandchildren = convertToOriginalForm(None, child.clone());
-                // check if the child is empty
-                for child_ in grandchildren {
-                    if (child.is_single_child()) {
-                        if (results.is_empty()) {
-                            prefix_ = combineArcHs(Some(prefix_), child_);
-                        } else {
-                            for result in results {
-                                let n = combineArcHs(Some(result), child_.clone());
-                                results_with_tails.push(n);
+            // EVAL statements, however deep, are forms of their own: they are never joined
+            // onto the prefix, and only kept once when the prefix fans out
+            let mut evals: Vec<OriginalArcHForm> = vec![];
+            // the prefix fans out itself when it ends in a {set}: the children follow every path
+            let mut all_results: Vec<OriginalArcHForm> = vec![];
+            let prefix_forms = convertToOriginalForm(prefixFromOutside, *prefix);
+            for (path, prefix_form) in prefix_forms.into_iter().enumerate() {
+                let mut results: Vec<OriginalArcHForm> = vec![];
+                let mut results_with_tails: Vec<OriginalArcHForm> = vec![];
+                let mut prefix_ = prefix_form;
+                for child in children.iter() {
+                    // println!("\n\nchild: {:?}\n", child);
+                    let grandchildren = convertToOriginalForm(None, child.clone());
+                    // check if the child is empty
+                    for child_ in grandchildren {
+                        if child_.is_eval() {
+                            if path == 0 {
+                                evals.push(child_);
+                            }
+                        } else if (child.is_single_child()) {
+                            if (results.is_empty()) {
+                                prefix_ = combineArcHs(Some(prefix_), child_);
+                            } else {
+                                for result in results {
+                                    let n = combineArcHs(Some(result), child_.clone());
+                                    results_with_tails.push(n);
+                                }
+                                results = results_with_tails.clone();
+                                results_with_tails = vec![];
                             }
-                            results = results_with_tails.clone();
-                            results_with_tails = vec![];
+                        } else {
+                            let n = combineArcHs(Some(prefix_.clone()), child_);
+                            results.push(n);
                         }
-                    } else {
-                        let n = combineArcHs(Some(prefix_.clone()), child_);
-                        results.push(n);
                     }
                 }
+                // no `- ` child: every child went on from the chain, which is the one form
+                if results.is_empty() {
+                    results.push(prefix_);
+                }
+                all_results.extend(results);
             }
-            return results;
+            all_results.extend(evals);
+            return all_results;
         }
-        ArcH::EvalStatement { expression } => {
+        ArcH::EvalStatement { expression, parsed } => {
             return vec![OriginalArcHForm {
                 vf_pairs: vec![],
-                last_point: Vertex(vec![]),
+                last_point: Vertex::new(vec![]),
                 executable_expression: expression,
+                expression: parsed,
             }];
         }
     }
 }
 
+// Join two forms, arcH2 following on from arcH1:
+// - arcH1 ends in a placeholder (`A ><x>` followed by `- B`): arcH2 takes its place
+// - arcH2 starts with one (`- ><x> B` under `A`): arcH1's last point takes its place
+// - otherwise the two are joined with an empty fish
 pub fn combineArcHs(arcH1_: Option<OriginalArcHForm>, arcH2: OriginalArcHForm) -> OriginalArcHForm {
-    if (arcH1_.is_none()) {
+    let Some(arcH1) = arcH1_ else {
         return arcH2;
-    }
-    let arcH1 = arcH1_.unwrap();
-    if (arcH1.last_point.0.first().unwrap().is_empty()) {
-        // speical case 1: empty last point in arcH1
-        let mut extended_vf_pairs = arcH1.vf_pairs.clone();
-        extended_vf_pairs.extend(arcH2.vf_pairs.clone());
-        return OriginalArcHForm {
-            vf_pairs: extended_vf_pairs,
-            last_point: arcH2.last_point,
-            executable_expression: "".to_string(),
-        };
+    };
+    let mut extended_vf_pairs = arcH1.vf_pairs;
+    if arcH1.last_point.is_placeholder() {
+        extended_vf_pairs.extend(arcH2.vf_pairs);
     } else {
-        if (!arcH2.vf_pairs.is_empty()) {
-            let headV = arcH2.vf_pairs.first().unwrap().0.clone();
-            let headF = arcH2.vf_pairs.first().unwrap().1.clone();
-            let tail = arcH2.vf_pairs[1..].to_vec();
-            if (headV.0.first().unwrap().is_empty()) {
-                // speical case 2: empty first point in arcH2
-                let mut extended_vf_pairs = arcH1.vf_pairs.clone();
-                extended_vf_pairs.push((arcH1.last_point.clone(), headF.clone()));
-                extended_vf_pairs.extend(tail.clone());
-                return OriginalArcHForm {
-                    vf_pairs: extended_vf_pairs,
-                    last_point: arcH2.last_point,
-                    executable_expression: "".to_string(),
-                };
+        match arcH2.vf_pairs.split_first() {
+            Some(((headV, headF), tail)) if headV.is_placeholder() => {
+                extended_vf_pairs.push((arcH1.last_point, headF.clone()));
+                extended_vf_pairs.extend_from_slice(tail);
+            }
+            _ => {
+                extended_vf_pairs.push((arcH1.last_point, Fish::new("")));
+                extended_vf_pairs.extend(arcH2.vf_pairs);
             }
         }
-        // lastly, here is the default case where we connect them with an empty fish
-        let mut extended_vf_pairs = arcH1.vf_pairs.clone();
-        extended_vf_pairs.push((arcH1.last_point.clone(), Fish("".to_string())));
-        extended_vf_pairs.extend(arcH2.vf_pairs.clone());
-        return OriginalArcHForm {
-            vf_pairs: extended_vf_pairs,
-            last_point: arcH2.last_point,
-            executable_expression: "".to_string(),
-        };
+    }
+    OriginalArcHForm {
+        vf_pairs: extended_vf_pairs,
+        last_point: arcH2.last_point,
+        executable_expression: "".to_string(),
+        expression: None,
     }
 }
 
@@ -267,7 +604,7 @@ impl ArcH {
             | ArcH::ArcHWithNewLines {
                 is_single_child, ..
             } => *is_single_child,
-            _ => true,
+            ArcH::EvalStatement { .. } => false,
         }
     }
 }
@@ -0,0 +1,267 @@
+use crate::arch::{OriginalArcHForm, Vertex};
+use crate::{fence, set};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// Attributes: `{key=value, ...}` right after a vertex, or after the label inside a fish:
+//
+//   UI::App{owner="ui-team", weight=3} ><calls{timeout=5s, async}> API::Tasks
+//
+// A value is a "string", a number, true or false, a [list, of, values], or a bare word
+// such as 5s, which is a string; a key on its own is true. Attributes say something about
+// a vertex or an edge without changing which one it is: `A{weight=3}` is still `A`.
+pub type Attributes = BTreeMap<String, AttributeValue>;
+
+// In JSON a value is the matching JSON value, e.g. "5s", 3, true or ["a", 1]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum AttributeValue {
+    Bool(bool),
+    Number(serde_json::Number),
+    String(String),
+    List(Vec<AttributeValue>),
+}
+
+// How far to jump over a `"..."` or a `{ ... }` group at the start of `rest`, if it is one
+pub fn group_len(rest: &str) -> Option<usize> {
+    if let Some(literal) = rest.strip_prefix('"') {
+        return Some(literal.find('"').map_or(rest.len(), |end| end + 2));
+    }
+    if rest.starts_with('{') {
+        return Some(set::end(rest).unwrap_or(rest.len()));
+    }
+    None
+}
+
+// Where `needle` first occurs in `s` outside attribute blocks and quotes
+pub fn find(s: &str, needle: &str) -> Option<usize> {
+    let mut i = 0;
+    while i < s.len() {
+        let rest = &s[i..];
+        if rest.starts_with(needle) {
+            return Some(i);
+        }
+        i += group_len(rest).unwrap_or_else(|| rest.chars().next().map_or(1, char::len_utf8));
+    }
+    None
+}
+
+// Split the attribute block off the end of `s`: "A{x=1}" gives ("A", Some("{x=1}")).
+// `leading`: a block at the very start is attributes too (in a fish label), rather than a set.
+fn split_block(s: &str, leading: bool) -> (&str, Option<&str>) {
+    let s = s.trim();
+    if !s.ends_with('}') {
+        return (s, None);
+    }
+    let mut i = 0;
+    while i < s.len() {
+        let rest = &s[i..];
+        // `${NAME}` is a variable, not a block
+        if rest.starts_with('{')
+            && (i > 0 || leading)
+            && !s[..i].ends_with('$')
+            && set::end(rest) == Some(rest.len())
+        {
+            return (s[..i].trim_end(), Some(rest));
+        }
+        let skip = if let (0, Some((block, _))) = (i, fence::split_block(rest)) {
+            block.len()
+        } else if rest.starts_with("$(") {
+            crate::expression_end(rest).unwrap_or(rest.len())
+        } else if let Some(len) = group_len(rest) {
+            len
+        } else {
+            rest.chars().next().map_or(1, char::len_utf8)
+        };
+        i += skip;
+    }
+    (s, None)
+}
+
+// the vertex text and its attribute block; a `{set}` at the start is part of the vertex
+pub fn split_vertex(s: &str) -> (&str, Option<&str>) {
+    split_block(s, false)
+}
+
+// the fish label and its attribute block, e.g. "calls{async}" or "{async}"
+pub fn split_label(s: &str) -> (&str, Option<&str>) {
+    split_block(s, true)
+}
+
+// `s` split on the commas that are not inside quotes, [lists] or {blocks}
+fn items(s: &str) -> Vec<&str> {
+    let mut items = Vec::new();
+    let mut depth = 0;
+    let mut quoted = false;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '[' | '{' if !quoted => depth += 1,
+            ']' | '}' if !quoted => depth -= 1,
+            ',' if !quoted && depth == 0 => {
+                items.push(s[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    items.push(s[start..].trim());
+    items.retain(|item| !item.is_empty());
+    items
+}
+
+fn is_key(s: &str) -> bool {
+    !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '-' || c == '.')
+}
+
+fn parse_value(text: &str) -> Result<AttributeValue, String> {
+    if let Some(literal) = text.strip_prefix('"') {
+        return match literal.strip_suffix('"') {
+            Some(literal) if !literal.contains('"') => Ok(AttributeValue::String(literal.into())),
+            _ => Err(format!("`{}` is not a valid string", text)),
+        };
+    }
+    if let Some(list) = text.strip_prefix('[') {
+        let list = list
+            .strip_suffix(']')
+            .ok_or_else(|| format!("`{}` is missing its closing `]`", text))?;
+        return items(list)
+            .into_iter()
+            .map(parse_value)
+            .collect::<Result<_, _>>()
+            .map(AttributeValue::List);
+    }
+    if let Ok(b) = text.parse::<bool>() {
+        return Ok(AttributeValue::Bool(b));
+    }
+    if let Ok(n) = text.parse::<i64>() {
+        return Ok(AttributeValue::Number(n.into()));
+    }
+    if let Some(n) = text
+        .parse::<f64>()
+        .ok()
+        .and_then(serde_json::Number::from_f64)
+    {
+        return Ok(AttributeValue::Number(n));
+    }
+    // braces are fine: a template parameter is still `${param}` here
+    if text.contains(['"', '[', ']', '=']) {
+        return Err(format!("`{}` is not a valid value; quote it", text));
+    }
+    Ok(AttributeValue::String(text.into()))
+}
+
+// The attributes of a block found by split_vertex or split_label, e.g. "{timeout=5s, async}"
+pub fn parse(block: &str) -> Result<Attributes, String> {
+    let inner = &block[1..block.len() - 1];
+    let mut attributes = Attributes::new();
+    for item in items(inner) {
+        let (key, value) = match item.split_once('=') {
+            Some((key, value)) => (key.trim(), parse_value(value.trim())?),
+            None => (item, AttributeValue::Bool(true)),
+        };
+        if !is_key(key) {
+            return Err(format!("`{}` is not an attribute name", key));
+        }
+        if attributes.insert(key.to_string(), value).is_some() {
+            return Err(format!("attribute `{}` is set twice", key));
+        }
+    }
+    Ok(attributes)
+}
+
+// every string in the attributes, lists included, rewritten by `f` (e.g. template arguments)
+pub fn map_strings(attributes: &mut Attributes, f: &impl Fn(&str) -> String) {
+    fn value(v: &mut AttributeValue, f: &impl Fn(&str) -> String) {
+        match v {
+            AttributeValue::String(s) => *s = f(s),
+            AttributeValue::List(items) => items.iter_mut().for_each(|item| value(item, f)),
+            AttributeValue::Bool(_) | AttributeValue::Number(_) => {}
+        }
+    }
+    attributes.values_mut().for_each(|v| value(v, f));
+}
+
+// The attributes of every vertex of `forms`, merged from all the places it is written
+// (later ones win), so `A{x=1}` in one statement gives `A` its attributes everywhere
+pub fn of_vertices<'a>(
+    forms: impl IntoIterator<Item = &'a OriginalArcHForm>,
+) -> BTreeMap<Vertex, Attributes> {
+    let mut merged: BTreeMap<Vertex, Attributes> = BTreeMap::new();
+    for form in forms {
+        let vertices = form.vf_pairs.iter().map(|(v, _)| v);
+        for vertex in vertices.chain([&form.last_point]) {
+            merged
+                .entry(vertex.clone())
+                .or_default()
+                .extend(vertex.attributes.clone());
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn parsed(block: &str) -> serde_json::Value {
+        serde_json::to_value(parse(block).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn values() {
+        assert_eq!(
+            parsed(r#"{owner="ui-team", weight=3, ratio=0.5, timeout=5s, async, cached=false}"#),
+            json!({
+                "owner": "ui-team", "weight": 3, "ratio": 0.5, "timeout": "5s",
+                "async": true, "cached": false
+            })
+        );
+        assert_eq!(
+            parsed(r#"{tags=[core, "a, b", [1, 2]]}"#),
+            json!({ "tags": ["core", "a, b", [1, 2]] })
+        );
+        assert_eq!(parsed("{}"), json!({}));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            parse("{x=1, x=2}").unwrap_err(),
+            "attribute `x` is set twice"
+        );
+        assert_eq!(
+            parse("{a b=1}").unwrap_err(),
+            "`a b` is not an attribute name"
+        );
+        assert_eq!(
+            parse("{x=[1, 2}").unwrap_err(),
+            "`[1, 2` is missing its closing `]`"
+        );
+        assert_eq!(
+            parse("{x=a=b}").unwrap_err(),
+            "`a=b` is not a valid value; quote it"
+        );
+    }
+
+    #[test]
+    fn splitting_the_block_off() {
+        assert_eq!(split_vertex("A{x=1}"), ("A", Some("{x=1}")));
+        assert_eq!(split_vertex("{A, B}"), ("{A, B}", None));
+        assert_eq!(split_vertex("{A, B}{x=1}"), ("{A, B}", Some("{x=1}")));
+        assert_eq!(split_vertex("${T}::List"), ("${T}::List", None));
+        assert_eq!(split_vertex(r#""a{b}""#), (r#""a{b}""#, None));
+        assert_eq!(split_label("{async}"), ("", Some("{async}")));
+        assert_eq!(split_label("calls{async}"), ("calls", Some("{async}")));
+    }
+
+    #[test]
+    fn find_skips_blocks_and_quotes() {
+        assert_eq!(find(r#"A{x="><"} ><y> B"#, "><"), Some(10));
+        assert_eq!(find("A{x=1}", "x"), None);
+    }
+}
@@ -0,0 +1,121 @@
+use crate::fence;
+
+// Comments. `##` starts a comment that runs to the end of the line, at the start of a line
+// or after a statement:
+//
+//   UI::App ><renders> UI::List ## the main list
+//
+// Inside fenced blocks, $( ... ) expressions and "..." literals `##` is just text.
+// A line starting with `##{` comments out everything up to the matching `##}` line,
+// e.g. to switch off a whole subtree; these blocks nest.
+const BLOCK_OPEN: &str = "##{";
+const BLOCK_CLOSE: &str = "##}";
+
+// `line` up to its comment, if it has one
+fn before_comment(line: &str) -> &str {
+    let mut i = 0;
+    while i < line.len() {
+        let rest = &line[i..];
+        let skip = if rest.starts_with("##") {
+            return &line[..i];
+        } else if let Some((block, _)) = fence::split_block(rest) {
+            block.len()
+        } else if rest.starts_with("$(") {
+            crate::expression_end(rest).unwrap_or(rest.len())
+        } else if let Some(literal) = rest.strip_prefix('"') {
+            literal.find('"').map_or(rest.len(), |end| end + 2)
+        } else {
+            rest.chars().next().map_or(1, char::len_utf8)
+        };
+        i += skip;
+    }
+    line
+}
+
+// The lines without their comments; an unclosed `##{` is returned as the error
+pub fn strip(lines: Vec<&str>) -> Result<Vec<&str>, &str> {
+    let mut stripped = Vec::new();
+    // the `##{` lines of the open blocks
+    let mut open: Vec<&str> = Vec::new();
+    for line in lines {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with(BLOCK_OPEN) {
+            open.push(trimmed);
+        } else if trimmed.starts_with(BLOCK_CLOSE) && !open.is_empty() {
+            open.pop();
+        } else if open.is_empty() {
+            let code = before_comment(line).trim_end();
+            // a line that is only a comment goes entirely, so it cannot end a block of children
+            let had_comment = code.len() < line.trim_end().len();
+            if !had_comment || !code.trim().is_empty() {
+                stripped.push(code);
+            }
+        }
+    }
+    match open.first() {
+        Some(line) => Err(&line[..BLOCK_OPEN.len()]),
+        None => Ok(stripped),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{error, written};
+
+    fn stripped(input: &str) -> Vec<&str> {
+        strip(input.lines().collect()).unwrap()
+    }
+
+    #[test]
+    fn line_comments() {
+        assert_eq!(
+            stripped("## a model\nA ><x> B ## the edge\n  ## alone\n  - C\n\n"),
+            ["A ><x> B", "  - C", ""]
+        );
+    }
+
+    #[test]
+    fn block_comments_nest() {
+        let input = "A ><x> B\n##{\nC ><y> D\n  ##{ inner\n  - E\n  ##}\nF\n##} done\nG\n";
+        assert_eq!(stripped(input), ["A ><x> B", "G"]);
+        // a stray `##}` is a line comment
+        assert_eq!(stripped("A\n##}\nB\n"), ["A", "B"]);
+    }
+
+    #[test]
+    fn unclosed_block_comments() {
+        let input = "A\n##{\nB\n  ##{\n  ##}\n";
+        assert_eq!(strip(input.lines().collect()), Err("##{"));
+        assert_eq!(
+            error("[UI::App]\nA ><x> B\n##{\nC\n"),
+            ("block comment is never closed".to_string(), Some((3, 1)))
+        );
+    }
+
+    #[test]
+    fn hashes_in_literals_expressions_and_fences_are_text() {
+        assert_eq!(
+            before_comment("A ><x> \"#1 ## not\" ## yes"),
+            "A ><x> \"#1 ## not\" "
+        );
+        assert_eq!(
+            before_comment("A ><x> $(f(\"##\")) ## yes"),
+            "A ><x> $(f(\"##\")) "
+        );
+        assert_eq!(
+            before_comment("A ><x> ```\n## kept\n``` ## yes"),
+            "A ><x> ```\n## kept\n``` "
+        );
+        assert_eq!(written("A ><x> \"a ## b\"\n"), ["A ><x> \"a ## b\""]);
+    }
+
+    #[test]
+    fn hashes_in_fish_labels_start_a_comment() {
+        assert_eq!(before_comment("A ><x ## y> B"), "A ><x ");
+        assert_eq!(
+            error("A ><x ## y> B\n").0,
+            "`><x` is missing its closing `>` (`##` starts a comment)"
+        );
+    }
+}
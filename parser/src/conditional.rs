@@ -0,0 +1,96 @@
+use std::collections::BTreeSet;
+
+// Conditional blocks, for keeping several variants of an architecture in one file:
+//
+//   #if cache
+//   Logic::Tasks ><reads> Cache::Tasks ><misses> DB::Tasks
+//   #else
+//   Logic::Tasks ><reads> DB::Tasks
+//   #endif
+//
+//   [Cache::Tasks if cache]
+//
+// A condition is a flag name, or `!flag` for its absence. Blocks nest, and may contain
+// [section] headers. Flags are set with `--flag NAME` or ParseOptions::flags.
+pub type Flags = BTreeSet<String>;
+
+// An error in the directives, with the line it is about
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConditionError<'a> {
+    pub message: String,
+    pub line: &'a str,
+}
+
+fn holds<'a>(condition: &'a str, line: &'a str, flags: &Flags) -> Result<bool, ConditionError<'a>> {
+    let condition = condition.trim();
+    let (negated, flag) = match condition.strip_prefix('!') {
+        Some(flag) => (true, flag.trim()),
+        None => (false, condition),
+    };
+    if flag.is_empty() || flag.contains(char::is_whitespace) {
+        return Err(ConditionError {
+            message: format!("expected a flag or `!flag`, found `{}`", condition),
+            line,
+        });
+    }
+    Ok(flags.contains(flag) != negated)
+}
+
+// The lines that are in effect for `flags`, without the directives themselves
+pub fn select<'a>(lines: Vec<&'a str>, flags: &Flags) -> Result<Vec<&'a str>, ConditionError<'a>> {
+    // per open #if: the #if line, whether its condition held, whether #else was seen
+    let mut open: Vec<(&str, bool, bool)> = Vec::new();
+    let mut selected = Vec::new();
+    for line in lines {
+        let trimmed = line.trim();
+        let active = open.iter().all(|(_, held, in_else)| held != in_else);
+        if let Some(condition) = trimmed.strip_prefix("#if ") {
+            // conditions inside an inactive block are not evaluated
+            let held = !active || holds(condition, line, flags)?;
+            open.push((line, held, false));
+        } else if trimmed == "#else" {
+            match open.last_mut() {
+                Some((_, _, in_else @ false)) => *in_else = true,
+                Some(_) => {
+                    return Err(ConditionError {
+                        message: "`#else` after `#else`".to_string(),
+                        line,
+                    })
+                }
+                None => {
+                    return Err(ConditionError {
+                        message: "`#else` without `#if`".to_string(),
+                        line,
+                    })
+                }
+            }
+        } else if trimmed == "#endif" {
+            if open.pop().is_none() {
+                return Err(ConditionError {
+                    message: "`#endif` without `#if`".to_string(),
+                    line,
+                });
+            }
+        } else if active {
+            selected.push(line);
+        }
+    }
+    match open.pop() {
+        Some((line, _, _)) => Err(ConditionError {
+            message: "`#if` without `#endif`".to_string(),
+            line,
+        }),
+        None => Ok(selected),
+    }
+}
+
+// "Cache::Tasks if cache" -> ("Cache::Tasks", whether `cache` is set)
+pub fn section<'a>(name: &'a str, flags: &Flags) -> Result<(&'a str, bool), ConditionError<'a>> {
+    let split = name
+        .split_once(" if ")
+        .or_else(|| name.strip_suffix(" if").map(|section| (section, "")));
+    match split {
+        Some((section, condition)) => Ok((section.trim(), holds(condition, name, flags)?)),
+        None => Ok((name, true)),
+    }
+}
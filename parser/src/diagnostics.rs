@@ -0,0 +1,99 @@
+use crate::arch::Span;
+use serde::Serialize;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    pub fn from_name(name: &str) -> Option<Severity> {
+        match name {
+            "info" => Some(Severity::Info),
+            "warning" | "warn" => Some(Severity::Warning),
+            "error" => Some(Severity::Error),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Info => write!(f, "info"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+// A problem found in a .fish file, pointing back at the source text
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    // name of the rule that produced it, e.g. "self-loop"
+    pub code: String,
+    pub message: String,
+    pub span: Option<Span>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, code: &str, message: String, span: Option<Span>) -> Diagnostic {
+        Diagnostic {
+            severity,
+            code: code.to_string(),
+            message,
+            span,
+        }
+    }
+
+    // rustc-like rendering:
+    //
+    //   warning[self-loop]: `UI::List` points at itself via `renders`
+    //    --> app.fish:9:1
+    //     |
+    //   9 | UI::List ><renders> UI::List
+    //     | ^^^^^^^^
+    pub fn render(&self, path: &str, source: &str) -> String {
+        let mut s = format!("{}[{}]: {}\n", self.severity, self.code, self.message);
+        let span = match self.span {
+            Some(span) if span.start <= source.len() => span,
+            _ => {
+                s.push_str(&format!(" --> {}\n", path));
+                return s;
+            }
+        };
+        let (line, column) = span.line_col(source);
+        let text = source.lines().nth(line - 1).unwrap_or("");
+        let gutter = " ".repeat(line.to_string().len());
+        // only underline up to the end of the first line of the span
+        let width = text
+            .chars()
+            .skip(column - 1)
+            .take(span.end.saturating_sub(span.start))
+            .count()
+            .max(1);
+        s.push_str(&format!("{} --> {}:{}:{}\n", gutter, path, line, column));
+        s.push_str(&format!("{} |\n", gutter));
+        s.push_str(&format!("{} | {}\n", line, text));
+        s.push_str(&format!(
+            "{} | {}{}\n",
+            gutter,
+            " ".repeat(column - 1),
+            "^".repeat(width)
+        ));
+        s
+    }
+}
+
+// Print diagnostics to stderr; returns true if any of them is an error
+pub fn report(diagnostics: &[Diagnostic], path: &str, source: &str) -> bool {
+    for d in diagnostics {
+        eprintln!("{}", d.render(path, source));
+    }
+    diagnostics.iter().any(|d| d.severity == Severity::Error)
+}
@@ -0,0 +1,433 @@
+use crate::arch::{Fish, Vertex};
+use crate::graph::Graph;
+use crate::output::Edge;
+use crate::query::load_forms;
+use serde_json::json;
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+
+const USAGE: &str =
+    "usage: rust-parser diff [--format text|json|dot] [--vertices] [--exit-code] [--define NAME=VALUE]...
+                   [--flag NAME]... <old> <new>
+
+<old> and <new> are .fish files or models in JSON (e.g. output.json). Reindenting or
+reordering statements changes nothing; what is compared is the set of edges.
+
+As a git diff driver, which passes 7 arguments:
+  git config diff.lofish.command 'rust-parser diff'
+  echo '*.fish diff=lofish' >> .gitattributes
+As a git difftool:
+  git config difftool.lofish.cmd 'rust-parser diff \"$LOCAL\" \"$REMOTE\"'
+  git difftool -t lofish";
+
+type Triple = (Vertex, String, Vertex);
+
+// What changed between two models. Edges are compared after renames are applied, so a
+// renamed vertex does not also show up as removed and added edges.
+#[derive(Debug, Default)]
+pub struct Diff {
+    // old name -> new name: the vertex has exactly the same edges under its new name
+    pub renamed: BTreeMap<Vertex, Vertex>,
+    pub added: BTreeSet<Triple>,
+    pub removed: BTreeSet<Triple>,
+    // (from, old label, new label, to)
+    pub relabelled: BTreeSet<(Vertex, String, String, Vertex)>,
+    pub added_vertices: BTreeSet<Vertex>,
+    pub removed_vertices: BTreeSet<Vertex>,
+    // the edges of the new model that did not change, for context in the DOT output
+    pub unchanged: BTreeSet<Triple>,
+    pub vertices: BTreeSet<Vertex>,
+}
+
+fn triples(graph: &Graph) -> BTreeSet<Triple> {
+    graph
+        .vertices
+        .iter()
+        .flat_map(|from| {
+            graph
+                .edges_from(from)
+                .map(move |(label, to)| (from.clone(), label.clone(), to.clone()))
+        })
+        .collect()
+}
+
+// a vertex's edges, with the vertex itself left out (None) so they compare across names
+fn neighbourhood(graph: &Graph, vertex: &Vertex) -> BTreeSet<(bool, String, Option<Vertex>)> {
+    let other = |v: &Vertex| Some(v.clone()).filter(|v| v != vertex);
+    let outgoing = graph
+        .edges_from(vertex)
+        .map(|(label, to)| (true, label.clone(), other(to)));
+    let incoming = graph
+        .edges_to(vertex)
+        .map(|(label, from)| (false, label.clone(), other(from)));
+    outgoing.chain(incoming).collect()
+}
+
+// Edits (insertions, deletions, substitutions) that turn `a` into `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substituted = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substituted.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+// Names alike enough for a rename: moved to another namespace (UI::List -> Web::List), or
+// respelled within its own (UI::List -> UI::TaskList)
+fn similar(old: &Vertex, new: &Vertex) -> bool {
+    let (Some((old_name, old_namespace)), Some((new_name, new_namespace))) =
+        (old.segments.split_last(), new.segments.split_last())
+    else {
+        return false;
+    };
+    let longest = old_name.chars().count().max(new_name.chars().count());
+    old_name == new_name
+        || (old_namespace == new_namespace
+            && edit_distance(&old_name.to_lowercase(), &new_name.to_lowercase()) * 2 <= longest)
+}
+
+impl Diff {
+    pub fn new(old: &Graph, new: &Graph) -> Diff {
+        let gone: Vec<&Vertex> = old.vertices.difference(&new.vertices).collect();
+        let arrived: Vec<&Vertex> = new.vertices.difference(&old.vertices).collect();
+
+        // a rename is only assumed when exactly one new vertex fits, and vice versa. One
+        // edge alone does not tell a renamed leaf from a leaf replaced by another, so then
+        // the names must be alike too.
+        let mut candidates: BTreeMap<&Vertex, Vec<&Vertex>> = BTreeMap::new();
+        for n in &arrived {
+            let edges = neighbourhood(new, n);
+            if edges.is_empty() {
+                continue;
+            }
+            let fits =
+                |o: &Vertex| neighbourhood(old, o) == edges && (edges.len() > 1 || similar(o, n));
+            for o in gone.iter().filter(|o| fits(o)) {
+                candidates.entry(o).or_default().push(n);
+            }
+        }
+        let mut claims: BTreeMap<&Vertex, usize> = BTreeMap::new();
+        for n in candidates.values().flatten() {
+            *claims.entry(n).or_default() += 1;
+        }
+        let renamed: BTreeMap<Vertex, Vertex> = candidates
+            .into_iter()
+            .filter(|(_, ns)| ns.len() == 1 && claims[ns[0]] == 1)
+            .map(|(o, ns)| (o.clone(), ns[0].clone()))
+            .collect();
+
+        let rename = |v: &Vertex| renamed.get(v).unwrap_or(v).clone();
+        let old_edges: BTreeSet<Triple> = triples(old)
+            .into_iter()
+            .map(|(from, label, to)| (rename(&from), label, rename(&to)))
+            .collect();
+        let new_edges = triples(new);
+        let mut removed: BTreeSet<Triple> = old_edges.difference(&new_edges).cloned().collect();
+        let mut added: BTreeSet<Triple> = new_edges.difference(&old_edges).cloned().collect();
+
+        // between the same two vertices, a removed and an added edge are one relabelled edge
+        let mut relabelled = BTreeSet::new();
+        for (from, label, to) in removed.clone() {
+            let replacement = added
+                .iter()
+                .find(|(f, _, t)| *f == from && *t == to)
+                .cloned();
+            if let Some(replacement) = replacement {
+                removed.remove(&(from.clone(), label.clone(), to.clone()));
+                added.remove(&replacement);
+                relabelled.insert((from, label, replacement.1, to));
+            }
+        }
+
+        let renamed_to: BTreeSet<&Vertex> = renamed.values().collect();
+        Diff {
+            added_vertices: arrived
+                .iter()
+                .filter(|v| !renamed_to.contains(*v))
+                .map(|v| (*v).clone())
+                .collect(),
+            removed_vertices: gone
+                .iter()
+                .filter(|v| !renamed.contains_key(*v))
+                .map(|v| (*v).clone())
+                .collect(),
+            unchanged: new_edges.intersection(&old_edges).cloned().collect(),
+            vertices: new.vertices.clone(),
+            renamed,
+            added,
+            removed,
+            relabelled,
+        }
+    }
+
+    // `with_vertices`: also count vertices that were added or removed without an edge changing
+    pub fn is_empty(&self, with_vertices: bool) -> bool {
+        self.renamed.is_empty()
+            && self.added.is_empty()
+            && self.removed.is_empty()
+            && self.relabelled.is_empty()
+            && (!with_vertices
+                || (self.added_vertices.is_empty() && self.removed_vertices.is_empty()))
+    }
+}
+
+fn edge_text((from, label, to): &Triple) -> String {
+    format!("{} ><{}> {}", from.name(), label, to.name())
+}
+
+pub fn render_text(diff: &Diff, with_vertices: bool) -> String {
+    let mut out = String::new();
+    for (old, new) in &diff.renamed {
+        out += &format!("~ {} => {}\n", old.name(), new.name());
+    }
+    for (from, old, new, to) in &diff.relabelled {
+        out += &format!(
+            "~ {} ><{}> {} (was ><{}>)\n",
+            from.name(),
+            new,
+            to.name(),
+            old
+        );
+    }
+    for edge in &diff.removed {
+        out += &format!("- {}\n", edge_text(edge));
+    }
+    for edge in &diff.added {
+        out += &format!("+ {}\n", edge_text(edge));
+    }
+    if with_vertices {
+        for vertex in &diff.removed_vertices {
+            out += &format!("- vertex {}\n", vertex.name());
+        }
+        for vertex in &diff.added_vertices {
+            out += &format!("+ vertex {}\n", vertex.name());
+        }
+    }
+    out
+}
+
+fn edge_json((from, label, to): &Triple) -> serde_json::Value {
+    json!(Edge {
+        from: from.clone(),
+        fish: Fish::new(label),
+        to: to.clone(),
+    })
+}
+
+pub fn render_json(diff: &Diff, with_vertices: bool) -> serde_json::Value {
+    let renamed: Vec<_> = diff
+        .renamed
+        .iter()
+        .map(|(old, new)| json!({ "from": old, "to": new }))
+        .collect();
+    let relabelled: Vec<_> = diff
+        .relabelled
+        .iter()
+        .map(|(from, old, new, to)| {
+            json!({
+                "from": from,
+                "to": to,
+                "old_fish": Fish::new(old),
+                "new_fish": Fish::new(new),
+            })
+        })
+        .collect();
+    let mut value = json!({
+        "renamed": renamed,
+        "relabelled": relabelled,
+        "removed": diff.removed.iter().map(edge_json).collect::<Vec<_>>(),
+        "added": diff.added.iter().map(edge_json).collect::<Vec<_>>(),
+    });
+    if with_vertices {
+        value["removed_vertices"] = json!(diff.removed_vertices);
+        value["added_vertices"] = json!(diff.added_vertices);
+    }
+    value
+}
+
+fn dot_id(vertex: &Vertex) -> String {
+    format!("{:?}", vertex.name())
+}
+
+// The new graph, with changes in colour: green added, red removed, orange renamed or relabelled
+pub fn render_dot(diff: &Diff) -> String {
+    let mut out = String::from("digraph diff {\n  node [shape=box];\n");
+    for vertex in &diff.vertices {
+        let attributes = match diff.renamed.iter().find(|(_, new)| *new == vertex) {
+            Some((old, _)) => format!(
+                "label={:?}, color=orange, fontcolor=orange",
+                format!("{}\n(was {})", vertex.name(), old.name())
+            ),
+            None if diff.added_vertices.contains(vertex) => {
+                "color=green, fontcolor=green".to_string()
+            }
+            None => "color=gray40".to_string(),
+        };
+        out += &format!("  {} [{}];\n", dot_id(vertex), attributes);
+    }
+    for vertex in &diff.removed_vertices {
+        out += &format!(
+            "  {} [color=red, fontcolor=red, style=dashed];\n",
+            dot_id(vertex)
+        );
+    }
+    let mut edge = |(from, label, to): &Triple, attributes: &str| {
+        out += &format!(
+            "  {} -> {} [label={:?}, {}];\n",
+            dot_id(from),
+            dot_id(to),
+            label,
+            attributes
+        );
+    };
+    for e in &diff.unchanged {
+        edge(e, "color=gray40");
+    }
+    for e in &diff.added {
+        edge(e, "color=green, fontcolor=green");
+    }
+    for e in &diff.removed {
+        edge(e, "color=red, fontcolor=red, style=dashed");
+    }
+    for (from, old, new, to) in &diff.relabelled {
+        let label = format!("{} (was {})", new, old);
+        edge(
+            &(from.clone(), label, to.clone()),
+            "color=orange, fontcolor=orange",
+        );
+    }
+    out + "}\n"
+}
+
+// git passes /dev/null for the side of a file that does not exist
+fn load_graph(path: &str, options: &crate::ParseOptions) -> Result<Graph, Box<dyn Error>> {
+    if path == "/dev/null" {
+        return Ok(Graph::default());
+    }
+    Ok(Graph::new(&load_forms(path, options)?))
+}
+
+// `rust-parser diff ...`
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut format = "text";
+    let mut with_vertices = false;
+    let mut exit_code = false;
+    // both sides are parsed with the same options
+    let mut options = crate::ParseOptions::default();
+    let mut paths = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if options.take(arg, &mut args)? {
+            continue;
+        }
+        match arg.as_str() {
+            "--format" => match args.next().map(|f| f.as_str()) {
+                Some(f @ ("text" | "json" | "dot")) => format = f,
+                _ => crate::usage(USAGE),
+            },
+            "--vertices" => with_vertices = true,
+            "--exit-code" => exit_code = true,
+            _ => paths.push(arg.as_str()),
+        }
+    }
+    // as a git diff driver: path old-file old-hex old-mode new-file new-hex new-mode
+    let (name, old, new) = match paths[..] {
+        [old, new] => (None, old, new),
+        [name, old, _, _, new, _, _] => (Some(name), old, new),
+        _ => crate::usage(USAGE),
+    };
+
+    let diff = Diff::new(&load_graph(old, &options)?, &load_graph(new, &options)?);
+    match format {
+        "json" => println!(
+            "{}",
+            serde_json::to_string_pretty(&render_json(&diff, with_vertices))?
+        ),
+        "dot" => print!("{}", render_dot(&diff)),
+        _ => {
+            let text = render_text(&diff, with_vertices);
+            if let (Some(name), false) = (name, text.is_empty()) {
+                println!("lofish diff {}", name);
+            }
+            print!("{}", text);
+        }
+    }
+    // like `git diff --exit-code`; a git diff driver must exit 0
+    if exit_code && !diff.is_empty(with_vertices) {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph(source: &str) -> Graph {
+        Graph::new(&crate::testing::forms(source))
+    }
+
+    fn renames(old: &str, new: &str) -> Vec<(String, String)> {
+        Diff::new(&graph(old), &graph(new))
+            .renamed
+            .iter()
+            .map(|(o, n)| (o.name(), n.name()))
+            .collect()
+    }
+
+    fn pair(old: &str, new: &str) -> (String, String) {
+        (old.to_string(), new.to_string())
+    }
+
+    #[test]
+    fn a_vertex_with_the_same_edges_is_renamed() {
+        let old = "UI::App ><renders> UI::List ><calls> Logic::load\n";
+        let new = "UI::App ><renders> UI::Grid ><calls> Logic::load\n";
+        assert_eq!(renames(old, new), [pair("UI::List", "UI::Grid")]);
+    }
+
+    #[test]
+    fn a_leaf_replaced_by_another_is_not_a_rename() {
+        let old = "UI::App ><renders> UI::AddNewTask_Button\n";
+        let new = "UI::App ><renders> DB::X\n";
+        assert_eq!(renames(old, new), []);
+        let diff = Diff::new(&graph(old), &graph(new));
+        assert_eq!(diff.added.len(), 1);
+        assert_eq!(diff.removed.len(), 1);
+    }
+
+    #[test]
+    fn a_leaf_with_a_similar_name_is_renamed() {
+        let old = "UI::App ><renders> UI::List\n";
+        assert_eq!(
+            renames(old, "UI::App ><renders> UI::Lists\n"),
+            [pair("UI::List", "UI::Lists")]
+        );
+        assert_eq!(
+            renames(old, "UI::App ><renders> Web::List\n"),
+            [pair("UI::List", "Web::List")]
+        );
+        assert_eq!(renames(old, "UI::App ><renders> UI::Header\n"), []);
+    }
+
+    #[test]
+    fn two_vertices_that_fit_are_not_a_rename() {
+        let old = "A ><x> B\n";
+        let new = "A ><x> C\nA ><x> D\n";
+        assert_eq!(renames(old, new), []);
+    }
+
+    #[test]
+    fn edit_distances() {
+        assert_eq!(edit_distance("list", "lists"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+    }
+}
@@ -0,0 +1,596 @@
+use crate::arch::Span;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+// The expression language of EVAL: statements
+//
+//   expr    := or
+//   or      := and ("||" and)*
+//   and     := cmp ("&&" cmp)*
+//   cmp     := unary (("==" | "!=" | "<" | "<=" | ">" | ">=") unary)?
+//   unary   := "!" unary | fish
+//   fish    := primary ("><" label ">" primary?)*
+//   primary := number | string | "true" | "false" | call | vertex | "(" expr ")"
+//   call    := name "(" (expr ("," expr)*)? ")"
+//   vertex  := segment ("::" segment)*        segments may be `*` wildcards
+//
+// e.g. `count(UI::App ><renders>) == 2` or `reaches(UI::*, DB::Users)`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Expr {
+    #[serde(flatten)]
+    pub kind: ExprKind,
+    #[serde(skip)]
+    pub span: Option<Span>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExprKind {
+    Number(i64),
+    String(String),
+    Bool(bool),
+    Vertex(Vec<String>),
+    // `source ><label>` or `source ><label> target`
+    Fish {
+        source: Box<Expr>,
+        label: String,
+        target: Option<Box<Expr>>,
+    },
+    Call {
+        name: String,
+        args: Vec<Expr>,
+    },
+    Not(Box<Expr>),
+    Binary {
+        op: BinOp,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BinOp {
+    #[serde(rename = "==")]
+    Eq,
+    #[serde(rename = "!=")]
+    Ne,
+    #[serde(rename = "<")]
+    Lt,
+    #[serde(rename = "<=")]
+    Le,
+    #[serde(rename = ">")]
+    Gt,
+    #[serde(rename = ">=")]
+    Ge,
+    #[serde(rename = "&&")]
+    And,
+    #[serde(rename = "||")]
+    Or,
+}
+
+impl fmt::Display for BinOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            BinOp::Eq => "==",
+            BinOp::Ne => "!=",
+            BinOp::Lt => "<",
+            BinOp::Le => "<=",
+            BinOp::Gt => ">",
+            BinOp::Ge => ">=",
+            BinOp::And => "&&",
+            BinOp::Or => "||",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExprError {
+    pub message: String,
+    pub span: Option<Span>,
+}
+
+impl fmt::Display for ExprError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    String(String),
+    Name(String),
+    Star,
+    PathSep,
+    LParen,
+    RParen,
+    Comma,
+    // `><label>`
+    Fish(String),
+    Op(BinOp),
+    Not,
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Number(n) => n.to_string(),
+        Token::String(s) => format!("{:?}", s),
+        Token::Name(n) => n.clone(),
+        Token::Star => "*".to_string(),
+        Token::PathSep => "::".to_string(),
+        Token::LParen => "(".to_string(),
+        Token::RParen => ")".to_string(),
+        Token::Comma => ",".to_string(),
+        Token::Fish(label) => format!("><{}>", label),
+        Token::Op(op) => op.to_string(),
+        Token::Not => "!".to_string(),
+    }
+}
+
+// token plus its byte range within the expression text
+type Spanned = (Token, usize, usize);
+
+fn lex(text: &str) -> Result<Vec<Spanned>, (String, usize, usize)> {
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < text.len() {
+        let rest = &text[i..];
+        let c = rest.chars().next().unwrap();
+        let start = i;
+        if c.is_whitespace() {
+            i += c.len_utf8();
+            continue;
+        }
+        let (token, len) = if let Some(fish) = rest.strip_prefix("><") {
+            match fish.find('>') {
+                Some(end) => (Token::Fish(fish[..end].trim().to_string()), end + 3),
+                None => return Err(("fish is missing its closing `>`".to_string(), i, text.len())),
+            }
+        } else if rest.starts_with("::") {
+            (Token::PathSep, 2)
+        } else if rest.starts_with("==") {
+            (Token::Op(BinOp::Eq), 2)
+        } else if rest.starts_with("!=") {
+            (Token::Op(BinOp::Ne), 2)
+        } else if rest.starts_with("<=") {
+            (Token::Op(BinOp::Le), 2)
+        } else if rest.starts_with(">=") {
+            (Token::Op(BinOp::Ge), 2)
+        } else if rest.starts_with("&&") {
+            (Token::Op(BinOp::And), 2)
+        } else if rest.starts_with("||") {
+            (Token::Op(BinOp::Or), 2)
+        } else if c == '"' {
+            let mut value = String::new();
+            let mut chars = rest.char_indices().skip(1);
+            let mut end = None;
+            while let Some((j, ch)) = chars.next() {
+                match ch {
+                    '"' => {
+                        end = Some(j + 1);
+                        break;
+                    }
+                    '\\' => match chars.next() {
+                        Some((_, 'n')) => value.push('\n'),
+                        Some((_, 't')) => value.push('\t'),
+                        Some((_, other)) => value.push(other),
+                        None => break,
+                    },
+                    other => value.push(other),
+                }
+            }
+            match end {
+                Some(len) => (Token::String(value), len),
+                None => return Err(("unterminated string".to_string(), i, text.len())),
+            }
+        } else if c.is_ascii_digit()
+            || (c == '-' && rest[1..].starts_with(|d: char| d.is_ascii_digit()))
+        {
+            let len = 1 + rest[1..].chars().take_while(|d| d.is_ascii_digit()).count();
+            match rest[..len].parse() {
+                Ok(n) => (Token::Number(n), len),
+                Err(_) => return Err(("number out of range".to_string(), i, i + len)),
+            }
+        } else if c.is_alphanumeric() || c == '_' {
+            let len: usize = rest
+                .chars()
+                .take_while(|d| d.is_alphanumeric() || *d == '_')
+                .map(|d| d.len_utf8())
+                .sum();
+            (Token::Name(rest[..len].to_string()), len)
+        } else {
+            let token = match c {
+                '*' => Token::Star,
+                '(' => Token::LParen,
+                ')' => Token::RParen,
+                ',' => Token::Comma,
+                '<' => Token::Op(BinOp::Lt),
+                '>' => Token::Op(BinOp::Gt),
+                '!' => Token::Not,
+                _ => {
+                    let len = c.len_utf8();
+                    return Err((format!("unexpected `{}`", c), i, i + len));
+                }
+            };
+            (token, 1)
+        };
+        i += len;
+        tokens.push((token, start, i));
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Spanned>,
+    pos: usize,
+    // where the expression text starts in the source, if known
+    base: Option<usize>,
+    // byte length of the expression text
+    len: usize,
+}
+
+impl Parser {
+    fn span(&self, start: usize, end: usize) -> Option<Span> {
+        self.base.map(|base| Span {
+            start: base + start,
+            end: base + end,
+        })
+    }
+
+    fn error(&self, message: String, start: usize, end: usize) -> ExprError {
+        ExprError {
+            message,
+            span: self.span(start, end),
+        }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(t, _, _)| t)
+    }
+
+    // start offset of the next token, or the end of the text
+    fn here(&self) -> usize {
+        self.tokens
+            .get(self.pos)
+            .map(|(_, s, _)| *s)
+            .unwrap_or(self.len)
+    }
+
+    // end offset of the last consumed token
+    fn last_end(&self) -> usize {
+        match self.pos {
+            0 => 0,
+            p => self.tokens[p - 1].2,
+        }
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).map(|(t, _, _)| t.clone());
+        self.pos += 1;
+        token
+    }
+
+    fn unexpected(&self) -> ExprError {
+        match self.tokens.get(self.pos) {
+            Some((token, start, end)) => {
+                self.error(format!("unexpected `{}`", describe(token)), *start, *end)
+            }
+            None => self.error(
+                "unexpected end of expression".to_string(),
+                self.len,
+                self.len,
+            ),
+        }
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), ExprError> {
+        if self.peek() == Some(&expected) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            let mut err = self.unexpected();
+            err.message = format!("expected `{}`, {}", describe(&expected), err.message);
+            Err(err)
+        }
+    }
+
+    fn node(&self, kind: ExprKind, start: usize) -> Expr {
+        Expr {
+            kind,
+            span: self.span(start, self.last_end()),
+        }
+    }
+
+    fn binary(&self, op: BinOp, left: Expr, right: Expr, start: usize) -> Expr {
+        self.node(
+            ExprKind::Binary {
+                op,
+                left: Box::new(left),
+                right: Box::new(right),
+            },
+            start,
+        )
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, ExprError> {
+        let start = self.here();
+        let mut left = self.parse_and()?;
+        while self.peek() == Some(&Token::Op(BinOp::Or)) {
+            self.pos += 1;
+            let right = self.parse_and()?;
+            left = self.binary(BinOp::Or, left, right, start);
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, ExprError> {
+        let start = self.here();
+        let mut left = self.parse_cmp()?;
+        while self.peek() == Some(&Token::Op(BinOp::And)) {
+            self.pos += 1;
+            let right = self.parse_cmp()?;
+            left = self.binary(BinOp::And, left, right, start);
+        }
+        Ok(left)
+    }
+
+    fn parse_cmp(&mut self) -> Result<Expr, ExprError> {
+        let start = self.here();
+        let left = self.parse_unary()?;
+        match self.peek() {
+            Some(Token::Op(op)) if !matches!(op, BinOp::And | BinOp::Or) => {
+                let op = *op;
+                self.pos += 1;
+                let right = self.parse_unary()?;
+                Ok(self.binary(op, left, right, start))
+            }
+            _ => Ok(left),
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, ExprError> {
+        let start = self.here();
+        if self.peek() == Some(&Token::Not) {
+            self.pos += 1;
+            let inner = self.parse_unary()?;
+            return Ok(self.node(ExprKind::Not(Box::new(inner)), start));
+        }
+        self.parse_fish()
+    }
+
+    fn parse_fish(&mut self) -> Result<Expr, ExprError> {
+        let start = self.here();
+        let mut source = self.parse_primary()?;
+        while let Some(Token::Fish(label)) = self.peek() {
+            let label = label.clone();
+            self.pos += 1;
+            let target = match self.peek() {
+                Some(Token::Number(_))
+                | Some(Token::String(_))
+                | Some(Token::Name(_))
+                | Some(Token::Star)
+                | Some(Token::LParen) => Some(Box::new(self.parse_primary()?)),
+                _ => None,
+            };
+            source = self.node(
+                ExprKind::Fish {
+                    source: Box::new(source),
+                    label,
+                    target,
+                },
+                start,
+            );
+        }
+        Ok(source)
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, ExprError> {
+        let start = self.here();
+        match self.peek().cloned() {
+            Some(Token::Number(n)) => {
+                self.pos += 1;
+                Ok(self.node(ExprKind::Number(n), start))
+            }
+            Some(Token::String(s)) => {
+                self.pos += 1;
+                Ok(self.node(ExprKind::String(s), start))
+            }
+            Some(Token::LParen) => {
+                self.pos += 1;
+                let inner = self.parse_or()?;
+                self.expect(Token::RParen)?;
+                Ok(inner)
+            }
+            Some(Token::Name(name))
+                if self.tokens.get(self.pos + 1).map(|t| &t.0) == Some(&Token::LParen) =>
+            {
+                self.pos += 2;
+                let mut args = Vec::new();
+                if self.peek() != Some(&Token::RParen) {
+                    args.push(self.parse_or()?);
+                    while self.peek() == Some(&Token::Comma) {
+                        self.pos += 1;
+                        args.push(self.parse_or()?);
+                    }
+                }
+                self.expect(Token::RParen)?;
+                Ok(self.node(ExprKind::Call { name, args }, start))
+            }
+            Some(Token::Name(_)) | Some(Token::Star) => {
+                let mut segments = vec![self.parse_segment()?];
+                while self.peek() == Some(&Token::PathSep) {
+                    self.pos += 1;
+                    segments.push(self.parse_segment()?);
+                }
+                let kind = match segments.as_slice() {
+                    [s] if s == "true" => ExprKind::Bool(true),
+                    [s] if s == "false" => ExprKind::Bool(false),
+                    _ => ExprKind::Vertex(segments),
+                };
+                Ok(self.node(kind, start))
+            }
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    fn parse_segment(&mut self) -> Result<String, ExprError> {
+        match self.peek() {
+            Some(Token::Name(_)) | Some(Token::Star) => {}
+            _ => return Err(self.unexpected()),
+        }
+        // `**` is two stars in a row
+        match self.next() {
+            Some(Token::Star) if self.peek() == Some(&Token::Star) => {
+                self.pos += 1;
+                Ok("**".to_string())
+            }
+            Some(Token::Star) => Ok("*".to_string()),
+            Some(Token::Name(name)) => Ok(name),
+            _ => unreachable!(),
+        }
+    }
+}
+
+// Parse the text of an EVAL: statement. `base` is where the text sits in the
+// source, so that the spans of the nodes (and of errors) point into the file.
+pub fn parse(text: &str, base: Option<Span>) -> Result<Expr, ExprError> {
+    let base = base.map(|s| s.start);
+    let tokens = lex(text).map_err(|(message, start, end)| ExprError {
+        message,
+        span: base.map(|b| Span {
+            start: b + start,
+            end: b + end,
+        }),
+    })?;
+    let mut parser = Parser {
+        tokens,
+        pos: 0,
+        base,
+        len: text.len(),
+    };
+    if parser.tokens.is_empty() {
+        return Err(parser.error("empty expression".to_string(), 0, text.len()));
+    }
+    let expr = parser.parse_or()?;
+    if parser.pos < parser.tokens.len() {
+        return Err(parser.unexpected());
+    }
+    Ok(expr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the tree as an s-expression, e.g. `(|| a (&& b c))`
+    fn shape(expr: &Expr) -> String {
+        match &expr.kind {
+            ExprKind::Number(n) => n.to_string(),
+            ExprKind::String(s) => format!("{:?}", s),
+            ExprKind::Bool(b) => b.to_string(),
+            ExprKind::Vertex(segments) => segments.join("::"),
+            ExprKind::Fish {
+                source,
+                label,
+                target,
+            } => match target {
+                Some(target) => format!("(><{}> {} {})", label, shape(source), shape(target)),
+                None => format!("(><{}> {})", label, shape(source)),
+            },
+            ExprKind::Call { name, args } => {
+                let args: Vec<String> = args.iter().map(shape).collect();
+                format!("({} {})", name, args.join(" "))
+            }
+            ExprKind::Not(inner) => format!("(! {})", shape(inner)),
+            ExprKind::Binary { op, left, right } => {
+                format!("({} {} {})", op, shape(left), shape(right))
+            }
+        }
+    }
+
+    fn parsed(text: &str) -> String {
+        shape(&parse(text, None).unwrap())
+    }
+
+    // the error message and where it points, with the text starting at offset 10
+    fn error(text: &str) -> (String, (usize, usize)) {
+        let err = parse(
+            text,
+            Some(Span {
+                start: 10,
+                end: 10 + text.len(),
+            }),
+        )
+        .unwrap_err();
+        let span = err.span.unwrap();
+        (err.message, (span.start, span.end))
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(parsed("a || b && c"), "(|| a (&& b c))");
+        assert_eq!(parsed("a && b || c"), "(|| (&& a b) c)");
+        assert_eq!(parsed("!a == b && c"), "(&& (== (! a) b) c)");
+        assert_eq!(parsed("(a || b) && !!c"), "(&& (|| a b) (! (! c)))");
+        assert_eq!(
+            parsed("count(UI::App ><renders>) >= 2"),
+            "(>= (count (><renders> UI::App)) 2)"
+        );
+        // fish application binds tighter than anything else, from left to right
+        assert_eq!(parsed("!A ><x> B ><y>"), "(! (><y> (><x> A B)))");
+    }
+
+    #[test]
+    fn values() {
+        assert_eq!(parsed("UI::*::**"), "UI::*::**");
+        assert_eq!(parsed("true || false"), "(|| true false)");
+        assert_eq!(
+            parsed(r#"assert(-3 < 0, "a \"b\"")"#),
+            r#"(assert (< -3 0) "a \"b\"")"#
+        );
+        assert_eq!(parsed("vertices()"), "(vertices )");
+    }
+
+    #[test]
+    fn spans() {
+        let expr = parse("count(A) == 2", Some(Span { start: 4, end: 17 })).unwrap();
+        assert_eq!(expr.span, Some(Span { start: 4, end: 17 }));
+        let ExprKind::Binary { left, right, .. } = expr.kind else {
+            panic!("expected a comparison");
+        };
+        assert_eq!(left.span, Some(Span { start: 4, end: 12 }));
+        assert_eq!(right.span, Some(Span { start: 16, end: 17 }));
+        // without a base there is nothing to point at
+        assert_eq!(parse("a", None).unwrap().span, None);
+        assert_eq!(parse("a &&", None).unwrap_err().span, None);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(error(""), ("empty expression".to_string(), (10, 10)));
+        assert_eq!(error("1 + 2"), ("unexpected `+`".to_string(), (12, 13)));
+        assert_eq!(
+            error(r#"count("a)"#),
+            ("unterminated string".to_string(), (16, 19))
+        );
+        assert_eq!(
+            error("A ><x B"),
+            ("fish is missing its closing `>`".to_string(), (12, 17))
+        );
+        assert_eq!(
+            error("count(A"),
+            (
+                "expected `)`, unexpected end of expression".to_string(),
+                (17, 17)
+            )
+        );
+        // comparisons do not chain
+        assert_eq!(error("1 < 2 < 3"), ("unexpected `<`".to_string(), (16, 17)));
+        assert_eq!(error("A:: && B"), ("unexpected `&&`".to_string(), (14, 16)));
+        assert_eq!(
+            error("99999999999999999999"),
+            ("number out of range".to_string(), (10, 30))
+        );
+    }
+}
@@ -0,0 +1,176 @@
+use crate::arch::Span;
+use crate::attributes;
+
+// Fenced blocks: a run of at least three backticks opens a code vertex wherever a vertex
+// may start (a statement, a `- ` child, or after a fish), with an optional language tag:
+//
+//   Logic::report ><runs> ```sql
+//     SELECT * FROM tasks
+//   ``` ><feeds> UI::Report
+//
+// The block ends at the first line starting with at least as many backticks, so ````
+// fences can embed ```. That line may go on with the rest of the chain, starting with the
+// block's {attributes} if it has any. The common indentation of the block's lines is removed.
+const MIN_FENCE: usize = 3;
+
+fn backticks(s: &str) -> usize {
+    s.chars().take_while(|c| *c == '`').count()
+}
+
+// where a fence opens in `line`: (byte offset, fence length)
+fn opening(line: &str) -> Option<(usize, usize)> {
+    // backticks in a "literal" are text
+    let start = attributes::find(line, "```")?;
+    let before = line[..start].trim();
+    if !(before.is_empty() || before == "-" || before.ends_with('>')) {
+        return None;
+    }
+    let len = backticks(&line[start..]);
+    // backticks in the tag make it inline code, not a fence
+    if line[start + len..].contains('`') {
+        return None;
+    }
+    Some((start, len))
+}
+
+fn closing(line: &str, len: usize) -> bool {
+    let line = line.trim_start();
+    let run = backticks(line);
+    // the chain may go on right after it, with the block's {attributes}
+    run >= len
+        && line[run..]
+            .chars()
+            .next()
+            .is_none_or(|c| c.is_whitespace() || c == '{')
+}
+
+// The lines of `input`, with every fenced block joined (newlines and all) into the line
+// that opens it. An unterminated fence is returned as the error.
+pub fn join_blocks(input: &str) -> Result<Vec<&str>, &str> {
+    let lines: Vec<&str> = input.lines().collect();
+    let mut joined = Vec::new();
+    let mut n = 0;
+    while n < lines.len() {
+        let first = lines[n];
+        let mut last = n;
+        // the closing line may go on with the chain, and open another block
+        let mut rest = first;
+        while let Some((start, len)) = opening(rest) {
+            let fence = &rest[start..];
+            last = (last + 1..lines.len())
+                .find(|&m| closing(lines[m], len))
+                .ok_or(fence)?;
+            let close = lines[last].trim_start();
+            rest = &close[backticks(close)..];
+        }
+        joined.push(
+            match (Span::within(input, first), Span::within(input, lines[last])) {
+                (Some(from), Some(to)) => &input[from.start..to.end],
+                _ => first,
+            },
+        );
+        n = last + 1;
+    }
+    Ok(joined)
+}
+
+// A fenced block at the start of `s`, and what follows its closing fence
+pub fn split_block(s: &str) -> Option<(&str, &str)> {
+    let len = backticks(s);
+    if len < MIN_FENCE || !s.contains('\n') {
+        return None;
+    }
+    let mut line_start = 0;
+    for line in s.split_inclusive('\n') {
+        if line_start > 0 && closing(line, len) {
+            let indent = line.len() - line.trim_start().len();
+            let end = line_start + indent + backticks(line.trim_start());
+            return Some((&s[..end], s[end..].trim()));
+        }
+        line_start += line.len();
+    }
+    None
+}
+
+// (language, text) of a block found by split_block
+pub fn content(block: &str) -> (Option<String>, String) {
+    let (first, body) = block.split_once('\n').unwrap_or((block, ""));
+    let language = first[backticks(first)..].trim();
+    let language = Some(language.to_string()).filter(|l| !l.is_empty());
+    // everything but the closing fence
+    let mut lines: Vec<&str> = body.lines().collect();
+    lines.pop();
+    let indent = lines
+        .iter()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| l.len() - l.trim_start().len())
+        .min()
+        .unwrap_or(0);
+    let text: Vec<&str> = lines
+        .iter()
+        .map(|l| l.get(indent..).unwrap_or("").trim_end())
+        .collect();
+    (language, text.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{error, written};
+
+    #[test]
+    fn blocks_are_joined_into_the_line_that_opens_them() {
+        let input = "A ><runs> ```sql\n  SELECT 1\n``` ><feeds> B\nC ><x> D\n";
+        assert_eq!(
+            join_blocks(input).unwrap(),
+            ["A ><runs> ```sql\n  SELECT 1\n``` ><feeds> B", "C ><x> D"]
+        );
+        // a longer fence embeds a shorter one
+        let input = "- ````\n```\n````\nE\n";
+        assert_eq!(join_blocks(input).unwrap(), ["- ````\n```\n````", "E"]);
+        // the closing line may open the next block
+        let input = "```\na\n``` ><x> ```\nb\n```\n";
+        assert_eq!(join_blocks(input).unwrap(), [input.trim_end()]);
+        // inline code is no fence
+        assert_eq!(join_blocks("A ><x> ```b```\nC\n").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn unterminated_fences() {
+        let input = "A ><x> B\nC ><runs> ```sql\nSELECT 1\n";
+        assert_eq!(join_blocks(input), Err("```sql"));
+        // a shorter run does not close a longer fence
+        assert_eq!(join_blocks("````\n```\n"), Err("````"));
+        assert_eq!(
+            error("[UI::App]\nC ><runs> ```sql\nSELECT 1\n"),
+            ("fenced block is never closed".to_string(), Some((2, 11)))
+        );
+    }
+
+    #[test]
+    fn fences_inside_literals_are_text() {
+        assert_eq!(
+            join_blocks("A ><x> \"a >```\"\nB\n").unwrap(),
+            ["A ><x> \"a >```\"", "B"]
+        );
+        assert_eq!(written("A ><x> \"```\"\n"), ["A ><x> \"```\""]);
+        // and a quote inside a block starts no literal
+        assert_eq!(
+            join_blocks("A ><x> ```\nsay \"hi\n```\nB\n").unwrap(),
+            ["A ><x> ```\nsay \"hi\n```", "B"]
+        );
+    }
+
+    #[test]
+    fn blocks_keep_their_language_and_lose_their_indentation() {
+        let (block, rest) =
+            split_block("```sql\n    SELECT *\n      FROM t\n  ```{x=1} ><y> Z").unwrap();
+        assert_eq!(rest, "{x=1} ><y> Z");
+        assert_eq!(
+            content(block),
+            (Some("sql".to_string()), "SELECT *\n  FROM t".to_string())
+        );
+        assert_eq!(content("```\nx\n```"), (None, "x".to_string()));
+        assert_eq!(split_block("```x```"), None);
+    }
+}
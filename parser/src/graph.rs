@@ -0,0 +1,211 @@
+use crate::arch::{OriginalArcHForm, Vertex};
+use crate::attributes::{self, Attributes};
+use crate::pattern::{glob, VertexPattern};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+// The graph spanned by the edges of the flattened forms.
+// Everything is kept in ordered collections, so traversals are deterministic.
+#[derive(Debug, Clone, Default)]
+pub struct Graph {
+    pub vertices: BTreeSet<Vertex>,
+    // vertex -> (fish label, neighbour)
+    outgoing: BTreeMap<Vertex, BTreeSet<(String, Vertex)>>,
+    incoming: BTreeMap<Vertex, BTreeSet<(String, Vertex)>>,
+    // the attributes of the vertices and edges that have any, merged from everywhere
+    // they are written
+    vertex_attributes: BTreeMap<Vertex, Attributes>,
+    edge_attributes: BTreeMap<(Vertex, String, Vertex), Attributes>,
+}
+
+// A fish filter: None follows every fish, Some(pattern) only the labels it globs
+pub type FishFilter<'a> = Option<&'a str>;
+
+fn fish_matches(filter: FishFilter, label: &str) -> bool {
+    match filter {
+        None => true,
+        Some(pattern) => glob(pattern, label),
+    }
+}
+
+impl Graph {
+    pub fn new(forms: &[OriginalArcHForm]) -> Graph {
+        let mut graph = Graph::default();
+        let mut vertex_attributes = attributes::of_vertices(forms);
+        vertex_attributes.retain(|_, a| !a.is_empty());
+        graph.vertex_attributes = vertex_attributes;
+        for form in forms {
+            // EVAL statements and placeholders are not vertices
+            if form.is_eval() {
+                continue;
+            }
+            if form.vf_pairs.is_empty() && !form.last_point.is_placeholder() {
+                graph.vertices.insert(form.last_point.clone());
+            }
+            for (from, fish, to) in form.edges() {
+                // fish-less joins only tie a statement to its section
+                if fish.label.is_empty() || from.is_placeholder() || to.is_placeholder() {
+                    continue;
+                }
+                graph.vertices.insert(from.clone());
+                graph.vertices.insert(to.clone());
+                graph
+                    .outgoing
+                    .entry(from.clone())
+                    .or_default()
+                    .insert((fish.label.clone(), to.clone()));
+                graph
+                    .incoming
+                    .entry(to.clone())
+                    .or_default()
+                    .insert((fish.label.clone(), from.clone()));
+                if !fish.attributes.is_empty() {
+                    graph
+                        .edge_attributes
+                        .entry((from.clone(), fish.label.clone(), to.clone()))
+                        .or_default()
+                        .extend(fish.attributes.clone());
+                }
+            }
+        }
+        graph
+    }
+
+    // (fish label, neighbour) pairs leaving a vertex
+    pub fn edges_from(&self, vertex: &Vertex) -> impl Iterator<Item = &(String, Vertex)> {
+        self.outgoing.get(vertex).into_iter().flatten()
+    }
+
+    pub fn edges_to(&self, vertex: &Vertex) -> impl Iterator<Item = &(String, Vertex)> {
+        self.incoming.get(vertex).into_iter().flatten()
+    }
+
+    pub fn vertex_attributes(&self, vertex: &Vertex) -> Option<&Attributes> {
+        self.vertex_attributes.get(vertex)
+    }
+
+    // the attributes of the fish of an edge, e.g. of ><calls{async}>
+    pub fn edge_attributes(&self, from: &Vertex, label: &str, to: &Vertex) -> Option<&Attributes> {
+        self.edge_attributes
+            .get(&(from.clone(), label.to_string(), to.clone()))
+    }
+
+    pub fn matching(&self, pattern: &VertexPattern) -> BTreeSet<Vertex> {
+        self.vertices
+            .iter()
+            .filter(|v| pattern.matches(v))
+            .cloned()
+            .collect()
+    }
+
+    pub fn successors(&self, from: &BTreeSet<Vertex>, fish: FishFilter) -> BTreeSet<Vertex> {
+        from.iter()
+            .flat_map(|v| self.edges_from(v))
+            .filter(|(label, _)| fish_matches(fish, label))
+            .map(|(_, to)| to.clone())
+            .collect()
+    }
+
+    pub fn predecessors(&self, to: &BTreeSet<Vertex>, fish: FishFilter) -> BTreeSet<Vertex> {
+        to.iter()
+            .flat_map(|v| self.edges_to(v))
+            .filter(|(label, _)| fish_matches(fish, label))
+            .map(|(_, from)| from.clone())
+            .collect()
+    }
+
+    // everything reachable in one or more steps (or, reversed, everything that reaches)
+    pub fn reachable(
+        &self,
+        start: &BTreeSet<Vertex>,
+        fish: FishFilter,
+        reverse: bool,
+    ) -> BTreeSet<Vertex> {
+        let mut seen = BTreeSet::new();
+        let mut queue: VecDeque<Vertex> = start.iter().cloned().collect();
+        while let Some(v) = queue.pop_front() {
+            let single = BTreeSet::from([v]);
+            let next = if reverse {
+                self.predecessors(&single, fish)
+            } else {
+                self.successors(&single, fish)
+            };
+            for n in next {
+                if seen.insert(n.clone()) {
+                    queue.push_back(n);
+                }
+            }
+        }
+        seen
+    }
+
+    // Simple paths (no repeated vertex) from any vertex of `from` to any of `to`,
+    // as alternating vertices and fish labels. Stops after `limit` paths.
+    pub fn paths(
+        &self,
+        from: &BTreeSet<Vertex>,
+        to: &BTreeSet<Vertex>,
+        fish: FishFilter,
+        limit: usize,
+    ) -> Vec<Path> {
+        let mut found = Vec::new();
+        for start in from {
+            let mut path = Path {
+                start: start.clone(),
+                steps: vec![],
+            };
+            self.walk(&mut path, to, fish, limit, &mut found);
+        }
+        found
+    }
+
+    fn walk(
+        &self,
+        path: &mut Path,
+        to: &BTreeSet<Vertex>,
+        fish: FishFilter,
+        limit: usize,
+        found: &mut Vec<Path>,
+    ) {
+        let here = path.end().clone();
+        for (label, next) in self.edges_from(&here) {
+            if found.len() >= limit {
+                return;
+            }
+            if !fish_matches(fish, label) || path.contains(next) {
+                continue;
+            }
+            path.steps.push((label.clone(), next.clone()));
+            if to.contains(next) {
+                found.push(path.clone());
+            }
+            self.walk(path, to, fish, limit, found);
+            path.steps.pop();
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Path {
+    pub start: Vertex,
+    // (fish label, vertex reached)
+    pub steps: Vec<(String, Vertex)>,
+}
+
+impl Path {
+    pub fn end(&self) -> &Vertex {
+        self.steps.last().map(|(_, v)| v).unwrap_or(&self.start)
+    }
+
+    fn contains(&self, vertex: &Vertex) -> bool {
+        &self.start == vertex || self.steps.iter().any(|(_, v)| v == vertex)
+    }
+
+    // UI::App ><renders> UI::List ><calls> Logic::load
+    pub fn to_fish(&self) -> String {
+        let mut s = self.start.name();
+        for (label, v) in &self.steps {
+            s.push_str(&format!(" ><{}> {}", label, v.name()));
+        }
+        s
+    }
+}
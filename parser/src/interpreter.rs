@@ -0,0 +1,512 @@
+use crate::arch::{OriginalArcHForm, Span, Vertex};
+use crate::diagnostics::{self, Diagnostic, Severity};
+use crate::expr::{BinOp, Expr, ExprKind};
+use crate::graph::{FishFilter, Graph, Path};
+use crate::pattern::VertexPattern;
+use serde_json::json;
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fmt;
+
+// paths() stops enumerating after this many paths
+pub const PATH_LIMIT: usize = 1000;
+
+const USAGE: &str =
+    "usage: rust-parser eval [--format text|json] [--define NAME=VALUE]... [--flag NAME]... <file>...";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Number(i64),
+    String(String),
+    Bool(bool),
+    Vertices(BTreeSet<Vertex>),
+    Paths(Vec<Path>),
+}
+
+impl Value {
+    pub fn truthy(&self) -> bool {
+        match self {
+            Value::Number(n) => *n != 0,
+            Value::String(s) => !s.is_empty(),
+            Value::Bool(b) => *b,
+            Value::Vertices(vs) => !vs.is_empty(),
+            Value::Paths(ps) => !ps.is_empty(),
+        }
+    }
+
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::Number(_) => "number",
+            Value::String(_) => "string",
+            Value::Bool(_) => "bool",
+            Value::Vertices(_) => "vertices",
+            Value::Paths(_) => "paths",
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            Value::Number(n) => json!(n),
+            Value::String(s) => json!(s),
+            Value::Bool(b) => json!(b),
+            Value::Vertices(vs) => json!(vs.iter().map(|v| v.name()).collect::<Vec<_>>()),
+            Value::Paths(ps) => json!(ps.iter().map(|p| p.to_fish()).collect::<Vec<_>>()),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Number(n) => write!(f, "{}", n),
+            Value::String(s) => write!(f, "{:?}", s),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Vertices(vs) => {
+                let names: Vec<String> = vs.iter().map(|v| v.name()).collect();
+                write!(f, "{{{}}}", names.join(", "))
+            }
+            Value::Paths(ps) => {
+                let paths: Vec<String> = ps.iter().map(|p| p.to_fish()).collect();
+                write!(f, "[{}]", paths.join(", "))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EvalError {
+    pub message: String,
+    pub span: Option<Span>,
+}
+
+fn error<T>(message: String, expr: &Expr) -> Result<T, EvalError> {
+    Err(EvalError {
+        message,
+        span: expr.span,
+    })
+}
+
+// Evaluates EVAL expressions against the graph of a model
+//
+//   vertex references   UI::App is that vertex, UI::* every vertex of the graph it matches
+//   A ><label>          the vertices A points at with that fish (`*` globs labels)
+//   A ><label> B        the same, restricted to B
+//
+//   count(x)                 number of vertices / paths, or length of a string
+//   exists(x)                whether any vertex of x is in the graph
+//   vertices()               every vertex of the graph
+//   successors(x, "fish"?)   one step forward, following any fish unless one is given
+//   predecessors(x, "fish"?) one step backward
+//   reachable(x, "fish"?)    one or more steps forward
+//   reaching(x, "fish"?)     one or more steps backward
+//   reaches(a, b, "fish"?)   whether some vertex of a reaches some vertex of b
+//   paths(a, b, "fish"?)     the simple paths from a to b
+//   assert(cond, "message"?) fails the evaluation unless cond holds
+pub struct Interpreter {
+    pub graph: Graph,
+}
+
+impl Interpreter {
+    pub fn new(forms: &[OriginalArcHForm]) -> Interpreter {
+        Interpreter {
+            graph: Graph::new(forms),
+        }
+    }
+
+    pub fn evaluate(&self, expr: &Expr) -> Result<Value, EvalError> {
+        match &expr.kind {
+            ExprKind::Number(n) => Ok(Value::Number(*n)),
+            ExprKind::String(s) => Ok(Value::String(s.clone())),
+            ExprKind::Bool(b) => Ok(Value::Bool(*b)),
+            ExprKind::Vertex(segments) => {
+                if segments.iter().any(|s| s.contains('*')) {
+                    let pattern = VertexPattern(segments.clone());
+                    Ok(Value::Vertices(self.graph.matching(&pattern)))
+                } else {
+                    Ok(Value::Vertices(BTreeSet::from([Vertex::new(
+                        segments.clone(),
+                    )])))
+                }
+            }
+            ExprKind::Fish {
+                source,
+                label,
+                target,
+            } => {
+                let source = self.vertices(source)?;
+                let mut reached = self.graph.successors(&source, Some(label.as_str()));
+                if let Some(target) = target {
+                    let target = self.vertices(target)?;
+                    reached.retain(|v| target.contains(v));
+                }
+                Ok(Value::Vertices(reached))
+            }
+            ExprKind::Not(inner) => Ok(Value::Bool(!self.evaluate(inner)?.truthy())),
+            ExprKind::Binary { op, left, right } => self.binary(*op, left, right),
+            ExprKind::Call { name, args } => self.call(expr, name, args),
+        }
+    }
+
+    fn vertices(&self, expr: &Expr) -> Result<BTreeSet<Vertex>, EvalError> {
+        match self.evaluate(expr)? {
+            Value::Vertices(vs) => Ok(vs),
+            other => error(
+                format!("expected vertices, found {}", other.type_name()),
+                expr,
+            ),
+        }
+    }
+
+    fn binary(&self, op: BinOp, left: &Expr, right: &Expr) -> Result<Value, EvalError> {
+        // && and || short-circuit
+        match op {
+            BinOp::And => {
+                let l = self.evaluate(left)?.truthy();
+                return Ok(Value::Bool(l && self.evaluate(right)?.truthy()));
+            }
+            BinOp::Or => {
+                let l = self.evaluate(left)?.truthy();
+                return Ok(Value::Bool(l || self.evaluate(right)?.truthy()));
+            }
+            _ => {}
+        }
+        let l = self.evaluate(left)?;
+        let r = self.evaluate(right)?;
+        let result = match (op, &l, &r) {
+            (BinOp::Eq, _, _) => l == r,
+            (BinOp::Ne, _, _) => l != r,
+            (_, Value::Number(a), Value::Number(b)) => match op {
+                BinOp::Lt => a < b,
+                BinOp::Le => a <= b,
+                BinOp::Gt => a > b,
+                _ => a >= b,
+            },
+            _ => {
+                return error(
+                    format!(
+                        "cannot compare {} and {} with `{}`",
+                        l.type_name(),
+                        r.type_name(),
+                        op
+                    ),
+                    right,
+                )
+            }
+        };
+        Ok(Value::Bool(result))
+    }
+
+    // the optional trailing "fish" argument of the graph functions
+    fn fish_arg<'a>(&self, args: &'a [Expr], at: usize) -> Result<FishFilter<'a>, EvalError> {
+        match args.get(at) {
+            None => Ok(None),
+            Some(Expr {
+                kind: ExprKind::String(label),
+                ..
+            }) => Ok(Some(label.as_str())),
+            Some(other) => error("expected a fish label string".to_string(), other),
+        }
+    }
+
+    fn call(&self, expr: &Expr, name: &str, args: &[Expr]) -> Result<Value, EvalError> {
+        let arity = |min: usize, max: usize| -> Result<(), EvalError> {
+            if args.len() < min || args.len() > max {
+                let expected = if min == max {
+                    min.to_string()
+                } else {
+                    format!("{} to {}", min, max)
+                };
+                return error(
+                    format!(
+                        "{}() takes {} argument(s), got {}",
+                        name,
+                        expected,
+                        args.len()
+                    ),
+                    expr,
+                );
+            }
+            Ok(())
+        };
+        match name {
+            "count" => {
+                arity(1, 1)?;
+                match self.evaluate(&args[0])? {
+                    Value::Vertices(vs) => Ok(Value::Number(vs.len() as i64)),
+                    Value::Paths(ps) => Ok(Value::Number(ps.len() as i64)),
+                    Value::String(s) => Ok(Value::Number(s.chars().count() as i64)),
+                    other => error(format!("cannot count a {}", other.type_name()), &args[0]),
+                }
+            }
+            "exists" => {
+                arity(1, 1)?;
+                let vs = self.vertices(&args[0])?;
+                Ok(Value::Bool(
+                    vs.iter().any(|v| self.graph.vertices.contains(v)),
+                ))
+            }
+            "vertices" => {
+                arity(0, 0)?;
+                Ok(Value::Vertices(self.graph.vertices.clone()))
+            }
+            "successors" | "predecessors" | "reachable" | "reaching" => {
+                arity(1, 2)?;
+                let vs = self.vertices(&args[0])?;
+                let fish = self.fish_arg(args, 1)?;
+                Ok(Value::Vertices(match name {
+                    "successors" => self.graph.successors(&vs, fish),
+                    "predecessors" => self.graph.predecessors(&vs, fish),
+                    "reachable" => self.graph.reachable(&vs, fish, false),
+                    _ => self.graph.reachable(&vs, fish, true),
+                }))
+            }
+            "reaches" => {
+                arity(2, 3)?;
+                let from = self.vertices(&args[0])?;
+                let to = self.vertices(&args[1])?;
+                let fish = self.fish_arg(args, 2)?;
+                let reached = self.graph.reachable(&from, fish, false);
+                Ok(Value::Bool(to.iter().any(|v| reached.contains(v))))
+            }
+            "paths" => {
+                arity(2, 3)?;
+                let from = self.vertices(&args[0])?;
+                let to = self.vertices(&args[1])?;
+                let fish = self.fish_arg(args, 2)?;
+                Ok(Value::Paths(self.graph.paths(&from, &to, fish, PATH_LIMIT)))
+            }
+            "assert" => {
+                arity(1, 2)?;
+                if self.evaluate(&args[0])?.truthy() {
+                    return Ok(Value::Bool(true));
+                }
+                let message = match args.get(1).map(|a| self.evaluate(a)).transpose()? {
+                    Some(Value::String(s)) => format!("assertion failed: {}", s),
+                    _ => "assertion failed".to_string(),
+                };
+                error(message, &args[0])
+            }
+            _ => error(format!("unknown function `{}`", name), expr),
+        }
+    }
+}
+
+// The outcome of one EVAL statement
+pub struct Outcome {
+    pub source: String,
+    pub span: Option<Span>,
+    pub result: Result<Value, EvalError>,
+}
+
+// Evaluate every EVAL statement of a model, in order, against the graph of the whole model
+pub fn run_forms(forms: &[OriginalArcHForm]) -> Vec<Outcome> {
+    let interpreter = Interpreter::new(forms);
+    forms
+        .iter()
+        .filter_map(|form| {
+            let expr = form.expression.as_ref()?;
+            Some(Outcome {
+                source: form.executable_expression.clone(),
+                span: expr.span,
+                result: interpreter.evaluate(expr),
+            })
+        })
+        .collect()
+}
+
+// `rust-parser eval ...`: exits with 1 if a statement fails, e.g. an assert()
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut as_json = false;
+    let mut options = crate::ParseOptions::default();
+    let mut paths = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if options.take(arg, &mut args)? {
+            continue;
+        }
+        match arg.as_str() {
+            "--format" => match args.next().map(|f| f.as_str()) {
+                Some("text") => as_json = false,
+                Some("json") => as_json = true,
+                _ => crate::usage(USAGE),
+            },
+            _ => paths.push(arg.clone()),
+        }
+    }
+    if paths.is_empty() {
+        crate::usage(USAGE);
+    }
+
+    let mut failed = false;
+    let mut results = Vec::new();
+    for path in paths {
+        let source = crate::read_source(&path).map_err(|e| format!("{}: {}", path, e))?;
+        let sections = match crate::parse_inputs_with(&source, &options) {
+            Ok(sections) => sections,
+            Err(e) => {
+                diagnostics::report(&[e.to_diagnostic()], &path, &source);
+                failed = true;
+                continue;
+            }
+        };
+        let forms: Vec<OriginalArcHForm> = crate::collect_inputs(&source, sections)
+            .into_iter()
+            .flat_map(|(_, forms)| forms)
+            .collect();
+        for outcome in run_forms(&forms) {
+            let line = outcome.span.map(|s| s.line_col(&source).0).unwrap_or(0);
+            match &outcome.result {
+                Ok(value) if as_json => results.push(json!({
+                    "file": path, "line": line, "expression": outcome.source, "value": value.to_json(),
+                })),
+                Ok(value) => println!("{}:{}: {} => {}", path, line, outcome.source, value),
+                Err(e) => {
+                    failed = true;
+                    if as_json {
+                        results.push(json!({
+                            "file": path, "line": line, "expression": outcome.source, "error": e.message,
+                        }));
+                    } else {
+                        println!("{}:{}: {} => FAILED", path, line, outcome.source);
+                        let d = Diagnostic::new(Severity::Error, "eval", e.message.clone(), e.span);
+                        eprintln!("{}", d.render(&path, &source));
+                    }
+                }
+            }
+        }
+    }
+    if as_json {
+        println!("{}", serde_json::to_string_pretty(&results)?);
+    }
+    if failed {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{forms, MODEL};
+
+    // every EVAL statement appended to MODEL, as its printed value or its error
+    fn results(evals: &[&str]) -> Vec<Result<String, String>> {
+        let mut source = MODEL.to_string();
+        for eval in evals {
+            source.push_str(&format!("EVAL: {}\n", eval));
+        }
+        run_forms(&forms(&source))
+            .into_iter()
+            .map(|o| o.result.map(|v| v.to_string()).map_err(|e| e.message))
+            .collect()
+    }
+
+    fn ok(value: &str) -> Result<String, String> {
+        Ok(value.to_string())
+    }
+
+    fn err(message: &str) -> Result<String, String> {
+        Err(message.to_string())
+    }
+
+    #[test]
+    fn graph_functions() {
+        assert_eq!(
+            results(&[
+                "UI::App ><renders>",
+                "UI::App ><renders> UI::Form",
+                "count(UI::*)",
+                "successors(Logic::*, \"re*\")",
+                "predecessors(DB::Tasks)",
+                "reachable(UI::List)",
+                "reaching(DB::Tasks, \"writes\")",
+                "reaches(UI::App, DB::*)",
+                "paths(UI::App, DB::Tasks)",
+                "exists(UI::Missing)",
+            ]),
+            [
+                ok("{UI::Form, UI::List}"),
+                ok("{UI::Form}"),
+                ok("3"),
+                ok("{DB::Tasks}"),
+                ok("{Logic::load, Logic::save}"),
+                ok("{DB::Tasks, Logic::load}"),
+                ok("{Logic::save}"),
+                ok("true"),
+                ok(
+                    "[UI::App ><renders> UI::Form ><submits> Logic::save ><writes> DB::Tasks, \
+                    UI::App ><renders> UI::List ><calls> Logic::load ><reads> DB::Tasks]"
+                ),
+                ok("false"),
+            ]
+        );
+    }
+
+    #[test]
+    fn section_joins_are_not_edges() {
+        // the statements are joined to `UI::App` without a fish, which is not an edge
+        assert_eq!(results(&["count(predecessors(UI::App))"]), [ok("0")]);
+    }
+
+    #[test]
+    fn operators() {
+        assert_eq!(
+            results(&[
+                "count(UI::App ><renders>) == 2 && !exists(UI::Missing)",
+                "count(\"abc\") >= 3 || unknown()",
+                "UI::List == UI::List",
+                "1 != 2",
+            ]),
+            [ok("true"), ok("true"), ok("true"), ok("true")]
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            results(&[
+                "unknown()",
+                "count(1)",
+                "count()",
+                "reaches(UI::App)",
+                "1 < \"a\"",
+                "successors(1)",
+                "successors(UI::App, UI::List)",
+                "assert(false, \"no way\")",
+                "assert(vertices() == UI::App)",
+            ]),
+            [
+                err("unknown function `unknown`"),
+                err("cannot count a number"),
+                err("count() takes 1 argument(s), got 0"),
+                err("reaches() takes 2 to 3 argument(s), got 1"),
+                err("cannot compare number and string with `<`"),
+                err("expected vertices, found number"),
+                err("expected a fish label string"),
+                err("assertion failed: no way"),
+                err("assertion failed"),
+            ]
+        );
+    }
+
+    #[test]
+    fn evals_under_a_statement_are_run() {
+        let source =
+            "A ><x> B\n  EVAL: assert(false, \"nested\")\n  - C\n    EVAL: count(A ><x>)\n";
+        let outcomes: Vec<_> = run_forms(&forms(source))
+            .into_iter()
+            .map(|o| o.result.map(|v| v.to_string()).map_err(|e| e.message))
+            .collect();
+        assert_eq!(outcomes, [err("assertion failed: nested"), ok("1")]);
+    }
+
+    #[test]
+    fn errors_point_at_their_argument() {
+        let outcome = run_forms(&forms("EVAL: count(1)\n")).remove(0);
+        assert_eq!(outcome.source, "count(1)");
+        assert_eq!(
+            outcome.result.unwrap_err().span,
+            Some(Span { start: 12, end: 13 })
+        );
+    }
+}
@@ -0,0 +1,551 @@
+use crate::arch::{ArcH, OriginalArcHForm, Span, Vertex};
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::schema;
+use crate::validate::{self, parse_level, Rule, RuleConfig};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+
+// picked up from the working directory when no --config is given
+pub const DEFAULT_CONFIG: &str = "lofish-lint.json";
+
+const USAGE: &str = "usage: rust-parser lint [--config FILE] [--format human|json|sarif] \
+                     [--rule RULE=SEVERITY]... [--define NAME=VALUE]... [--flag NAME]... <file>...";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Human,
+    Json,
+    Sarif,
+}
+
+// Load a rule configuration file, e.g.
+//
+//   {
+//     "rules": {
+//       "unknown-vertex": "off",
+//       "self-loop": "error",
+//       "fish-label-pattern": { "severity": "warning", "pattern": "^[a-z]+s$" },
+//       "top-level-namespace": { "enabled": true, "min-segments": 2 }
+//     }
+//   }
+pub fn load_config(path: &str) -> Result<RuleConfig, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    parse_config(&text).map_err(|e| format!("{}: {}", path, e))
+}
+
+pub fn parse_config(text: &str) -> Result<RuleConfig, String> {
+    let mut config = RuleConfig::default();
+    let value: Value = serde_json::from_str(text).map_err(|e| e.to_string())?;
+    let rules = match value.get("rules") {
+        Some(Value::Object(rules)) => rules,
+        Some(_) => return Err("`rules` must be an object".to_string()),
+        None => return Ok(config),
+    };
+    for (code, setting) in rules {
+        let rule = Rule::from_code(code).ok_or_else(|| format!("unknown rule `{}`", code))?;
+        match setting {
+            Value::String(level) => config.set(rule, parse_level(level)?),
+            Value::Bool(enabled) => config.set(rule, enabled_severity(rule, *enabled)),
+            Value::Object(options) => {
+                // listing options for a rule that is off by default turns it on
+                let mut severity = enabled_severity(rule, true);
+                let mut enabled = true;
+                for (key, option) in options {
+                    match (key.as_str(), option) {
+                        ("severity", Value::String(level)) => severity = parse_level(level)?,
+                        ("enabled", Value::Bool(b)) => enabled = *b,
+                        ("pattern", Value::String(pattern)) if rule == Rule::FishLabelPattern => {
+                            config.fish_label_pattern = regex::Regex::new(pattern)
+                                .map_err(|e| format!("{}: {}", code, e))?;
+                        }
+                        ("min-segments", Value::Number(n)) if rule == Rule::TopLevelNamespace => {
+                            config.namespace_depth = n
+                                .as_u64()
+                                .ok_or_else(|| format!("{}: min-segments must be a number", code))?
+                                as usize;
+                        }
+                        _ => return Err(format!("{}: invalid option `{}`", code, key)),
+                    }
+                }
+                config.set(rule, if enabled { severity } else { None });
+            }
+            _ => {
+                return Err(format!(
+                    "{}: expected a severity, a bool or an object",
+                    code
+                ))
+            }
+        }
+    }
+    Ok(config)
+}
+
+fn enabled_severity(rule: Rule, enabled: bool) -> Option<Severity> {
+    if enabled {
+        Some(rule.default_severity().unwrap_or(Severity::Warning))
+    } else {
+        None
+    }
+}
+
+// `## lofish-ignore: rule, rule` silences those rules on the next statement line,
+// `## lofish-ignore-file: rule` silences them in the whole file. `all` matches every rule.
+#[derive(Debug, Default)]
+struct Suppressions {
+    file: HashSet<String>,
+    lines: HashMap<usize, HashSet<String>>,
+}
+
+impl Suppressions {
+    fn scan(source: &str) -> Suppressions {
+        let mut found = Suppressions::default();
+        let mut pending: HashSet<String> = HashSet::new();
+        for (i, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if let Some(comment) = line.strip_prefix("##") {
+                let comment = comment.trim();
+                if let Some(rules) = comment.strip_prefix("lofish-ignore-file:") {
+                    found
+                        .file
+                        .extend(rules.split(',').map(|r| r.trim().to_string()));
+                } else if let Some(rules) = comment.strip_prefix("lofish-ignore:") {
+                    pending.extend(rules.split(',').map(|r| r.trim().to_string()));
+                }
+            } else if !line.is_empty() && !pending.is_empty() {
+                found.lines.insert(i + 1, std::mem::take(&mut pending));
+            }
+        }
+        found
+    }
+
+    fn covers(&self, diagnostic: &Diagnostic, source: &str) -> bool {
+        let matches = |rules: &HashSet<String>| {
+            rules.contains("all") || rules.contains(diagnostic.code.as_str())
+        };
+        if matches(&self.file) {
+            return true;
+        }
+        match diagnostic.span {
+            Some(span) => self
+                .lines
+                .get(&span.line_col(source).0)
+                .is_some_and(matches),
+            None => false,
+        }
+    }
+}
+
+// the vertex a statement starts with
+fn head(arch: &ArcH) -> Option<&Vertex> {
+    match arch {
+        ArcH::ArcH { vertex, .. } | ArcH::Single { vertex, .. } => Some(vertex),
+        ArcH::ArcHWithNewLines { prefix, .. } => head(prefix),
+        ArcH::EvalStatement { .. } => None,
+    }
+}
+
+// top-level-namespace works on the tree: it only looks at what was written at the top level
+fn check_top_level(
+    source: &str,
+    sections: &[(&str, Vec<ArcH>)],
+    config: &RuleConfig,
+) -> Vec<Diagnostic> {
+    let severity = match config.severity(Rule::TopLevelNamespace) {
+        Some(severity) => severity,
+        None => return vec![],
+    };
+    let mut heads: Vec<(Vec<String>, Option<Span>)> = Vec::new();
+    for (name, body) in sections.iter() {
+        if !name.is_empty() {
+            let segments = name.split("::").map(|s| s.trim().to_string()).collect();
+            heads.push((segments, Span::within(source, name)));
+        }
+        for arch in body {
+            // literals, code and expressions have no namespace, and lines that start with a
+            // fish continue the section header through their placeholder
+            if let Some(vertex) = head(arch).filter(|v| v.kind.is_path()) {
+                heads.push((vertex.segments.clone(), vertex.span));
+            }
+        }
+    }
+    heads
+        .into_iter()
+        .filter(|(segments, _)| segments.len() < config.namespace_depth)
+        .map(|(segments, span)| {
+            Diagnostic::new(
+                severity,
+                Rule::TopLevelNamespace.code(),
+                format!(
+                    "`{}` should have at least {} namespace segments",
+                    segments.join("::"),
+                    config.namespace_depth
+                ),
+                span,
+            )
+        })
+        .collect()
+}
+
+// Run all enabled rules over one source text, minus the suppressed ones. `options` must be
+// the ones the model was parsed with, or the rules check another model.
+pub fn lint_source(
+    source: &str,
+    options: &crate::ParseOptions,
+    config: &RuleConfig,
+) -> Vec<Diagnostic> {
+    let sections = match crate::parse_inputs_with(source, options) {
+        Ok(sections) => sections,
+        // nothing else can be checked without a model
+        Err(e) => return vec![e.to_diagnostic()],
+    };
+    let collected = crate::collect_inputs(source, sections.clone());
+    let mut found = validate::validate(&collected, config);
+    let forms: Vec<OriginalArcHForm> = collected.into_iter().flat_map(|(_, forms)| forms).collect();
+    found.extend(check_top_level(source, &sections, config));
+    let lines = crate::split_sections(source, options)
+        .expect("parse_inputs_with has split the sections already");
+    let (schema, schema_errors) = schema::parse_schema(source, &lines);
+    if let Some(schema) = schema {
+        found.extend(schema::check(&schema, &forms, config));
+    }
+    found.extend(schema_errors);
+    let ignored = Suppressions::scan(source);
+    found.retain(|d| !ignored.covers(d, source));
+    found.sort_by(|a, b| (a.span, &a.code, &a.message).cmp(&(b.span, &b.code, &b.message)));
+    found
+}
+
+pub struct FileReport {
+    pub path: String,
+    pub source: String,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+fn location(report: &FileReport, span: Option<Span>) -> (usize, usize, usize, usize) {
+    match span {
+        Some(span) => {
+            let (line, column) = span.line_col(&report.source);
+            let (end_line, end_column) = Span {
+                start: span.end,
+                end: span.end,
+            }
+            .line_col(&report.source);
+            (line, column, end_line, end_column)
+        }
+        None => (1, 1, 1, 1),
+    }
+}
+
+pub fn render_human(reports: &[FileReport]) -> String {
+    let mut s = String::new();
+    let mut counts: HashMap<Severity, usize> = HashMap::new();
+    for report in reports {
+        for d in &report.diagnostics {
+            s.push_str(&d.render(&report.path, &report.source));
+            s.push('\n');
+            *counts.entry(d.severity).or_default() += 1;
+        }
+    }
+    s.push_str(&format!(
+        "{} error(s), {} warning(s), {} info\n",
+        counts.get(&Severity::Error).unwrap_or(&0),
+        counts.get(&Severity::Warning).unwrap_or(&0),
+        counts.get(&Severity::Info).unwrap_or(&0)
+    ));
+    s
+}
+
+pub fn render_json(reports: &[FileReport]) -> Value {
+    let mut items = Vec::new();
+    for report in reports {
+        for d in &report.diagnostics {
+            let (line, column, end_line, end_column) = location(report, d.span);
+            items.push(json!({
+                "file": report.path,
+                "line": line,
+                "column": column,
+                "end_line": end_line,
+                "end_column": end_column,
+                "severity": d.severity,
+                "code": d.code,
+                "message": d.message,
+            }));
+        }
+    }
+    Value::Array(items)
+}
+
+// SARIF 2.1.0, as understood by GitHub code scanning and most CI dashboards
+pub fn render_sarif(reports: &[FileReport]) -> Value {
+    let rules: Vec<Value> = Rule::ALL
+        .iter()
+        .map(|r| json!({ "id": r.code(), "shortDescription": { "text": r.description() } }))
+        .collect();
+    let mut results = Vec::new();
+    for report in reports {
+        for d in &report.diagnostics {
+            let (line, column, end_line, end_column) = location(report, d.span);
+            let level = match d.severity {
+                Severity::Error => "error",
+                Severity::Warning => "warning",
+                Severity::Info => "note",
+            };
+            results.push(json!({
+                "ruleId": d.code,
+                "level": level,
+                "message": { "text": d.message },
+                "locations": [{
+                    "physicalLocation": {
+                        "artifactLocation": { "uri": report.path },
+                        "region": {
+                            "startLine": line,
+                            "startColumn": column,
+                            "endLine": end_line,
+                            "endColumn": end_column,
+                        }
+                    }
+                }]
+            }));
+        }
+    }
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": { "driver": { "name": "lofish-lint", "rules": rules } },
+            "results": results,
+        }]
+    })
+}
+
+// `rust-parser lint ...`
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut config_path = None;
+    let mut format = Format::Human;
+    let mut overrides = Vec::new();
+    let mut options = crate::ParseOptions::default();
+    let mut paths = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if options.take(arg, &mut args)? {
+            continue;
+        }
+        match arg.as_str() {
+            "--config" => {
+                config_path = Some(args.next().unwrap_or_else(|| crate::usage(USAGE)).clone())
+            }
+            "--rule" => overrides.push(args.next().unwrap_or_else(|| crate::usage(USAGE)).clone()),
+            "--format" => {
+                format = match args.next().map(|f| f.as_str()) {
+                    Some("human") => Format::Human,
+                    Some("json") => Format::Json,
+                    Some("sarif") => Format::Sarif,
+                    _ => crate::usage(USAGE),
+                }
+            }
+            _ => paths.push(arg.clone()),
+        }
+    }
+    if paths.is_empty() {
+        crate::usage(USAGE);
+    }
+
+    let mut config = match config_path {
+        Some(path) => load_config(&path)?,
+        None if std::path::Path::new(DEFAULT_CONFIG).exists() => load_config(DEFAULT_CONFIG)?,
+        None => RuleConfig::default(),
+    };
+    for spec in overrides {
+        config.apply_override(&spec)?;
+    }
+
+    let mut reports = Vec::new();
+    for path in paths {
+        let source = crate::read_source(&path).map_err(|e| format!("{}: {}", path, e))?;
+        let diagnostics = lint_source(&source, &options, &config);
+        reports.push(FileReport {
+            path,
+            source,
+            diagnostics,
+        });
+    }
+
+    match format {
+        Format::Human => print!("{}", render_human(&reports)),
+        Format::Json => println!("{}", serde_json::to_string_pretty(&render_json(&reports))?),
+        Format::Sarif => println!("{}", serde_json::to_string_pretty(&render_sarif(&reports))?),
+    }
+
+    let failed = reports
+        .iter()
+        .flat_map(|r| r.diagnostics.iter())
+        .any(|d| d.severity == Severity::Error);
+    if failed {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the codes and lines of what the linter finds in `source`
+    fn lint(source: &str, config: &RuleConfig) -> Vec<(String, usize)> {
+        lint_source(source, &crate::DEFAULT_OPTIONS, config)
+            .into_iter()
+            .map(|d| (d.code, d.span.map_or(0, |s| s.line_col(source).0)))
+            .collect()
+    }
+
+    fn report(source: &str) -> FileReport {
+        let config = parse_config(r#"{ "rules": { "unknown-vertex": "off" } }"#).unwrap();
+        FileReport {
+            path: "model.fish".to_string(),
+            source: source.to_string(),
+            diagnostics: lint_source(source, &crate::DEFAULT_OPTIONS, &config),
+        }
+    }
+
+    const LOOPS: &str = "[UI::App]\nUI::List ><x> UI::List\nUI::Form ><y> UI::Form\n";
+
+    #[test]
+    fn config_files() {
+        let config = parse_config(
+            r#"{
+              "rules": {
+                "unknown-vertex": "off",
+                "self-loop": "error",
+                "empty-fish": false,
+                "fish-case": true,
+                "fish-label-pattern": { "severity": "info", "pattern": "^[a-z]+s$" },
+                "top-level-namespace": { "min-segments": 3 },
+                "duplicate-edge": { "enabled": false }
+              }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(config.severity(Rule::UnknownVertex), None);
+        assert_eq!(config.severity(Rule::SelfLoop), Some(Severity::Error));
+        assert_eq!(config.severity(Rule::EmptyFish), None);
+        assert_eq!(config.severity(Rule::FishCase), Some(Severity::Warning));
+        assert_eq!(
+            config.severity(Rule::FishLabelPattern),
+            Some(Severity::Info)
+        );
+        assert!(config.fish_label_pattern.is_match("renders"));
+        assert!(!config.fish_label_pattern.is_match("render"));
+        // listing options turns a rule that is off by default on
+        assert_eq!(
+            config.severity(Rule::TopLevelNamespace),
+            Some(Severity::Warning)
+        );
+        assert_eq!(config.namespace_depth, 3);
+        assert_eq!(config.severity(Rule::DuplicateEdge), None);
+        // rules that are not mentioned keep their defaults
+        assert_eq!(
+            config.severity(Rule::SchemaViolation),
+            Some(Severity::Error)
+        );
+
+        assert!(parse_config("{}").is_ok());
+        assert_eq!(
+            parse_config(r#"{ "rules": [] }"#).unwrap_err(),
+            "`rules` must be an object"
+        );
+        assert_eq!(
+            parse_config(r#"{ "rules": { "no-such-rule": "off" } }"#).unwrap_err(),
+            "unknown rule `no-such-rule`"
+        );
+        assert_eq!(
+            parse_config(r#"{ "rules": { "self-loop": "loud" } }"#).unwrap_err(),
+            "unknown severity `loud`"
+        );
+        assert_eq!(
+            parse_config(r#"{ "rules": { "self-loop": { "pattern": "x" } } }"#).unwrap_err(),
+            "self-loop: invalid option `pattern`"
+        );
+        assert_eq!(
+            parse_config(r#"{ "rules": { "self-loop": 3 } }"#).unwrap_err(),
+            "self-loop: expected a severity, a bool or an object"
+        );
+    }
+
+    #[test]
+    fn ignore_comments() {
+        let config = parse_config(r#"{ "rules": { "unknown-vertex": "off" } }"#).unwrap();
+        assert_eq!(
+            lint(LOOPS, &config),
+            [("self-loop".to_string(), 2), ("self-loop".to_string(), 3)]
+        );
+
+        // only the next statement line, even after a blank line
+        let source = "[UI::App]\n## lofish-ignore: self-loop\n\nUI::List ><x> UI::List\nUI::Form ><y> UI::Form\n";
+        assert_eq!(lint(source, &config), [("self-loop".to_string(), 5)]);
+
+        // other rules are still reported on that line
+        let source =
+            "[UI::App]\n## lofish-ignore: empty-fish, duplicate-edge\nUI::List ><x> UI::List\n";
+        assert_eq!(lint(source, &config), [("self-loop".to_string(), 3)]);
+
+        let source = "[UI::App]\n## lofish-ignore: all\nUI::List ><x> UI::List\n";
+        assert_eq!(lint(source, &config), []);
+
+        let source = format!("## lofish-ignore-file: self-loop\n{}", LOOPS);
+        assert_eq!(lint(&source, &config), []);
+    }
+
+    #[test]
+    fn json_reports() {
+        let json = render_json(&[report(LOOPS)]);
+        assert_eq!(json.as_array().unwrap().len(), 2);
+        assert_eq!(
+            json[0],
+            json!({
+                "file": "model.fish",
+                "line": 2,
+                "column": 10,
+                "end_line": 2,
+                "end_column": 14,
+                "severity": "warning",
+                "code": "self-loop",
+                "message": "`UI::List` points at itself via `x`",
+            })
+        );
+    }
+
+    #[test]
+    fn sarif_reports() {
+        let sarif = render_sarif(&[report(LOOPS)]);
+        assert_eq!(sarif["version"], "2.1.0");
+        let run = &sarif["runs"][0];
+        let rules = run["tool"]["driver"]["rules"].as_array().unwrap();
+        assert_eq!(rules.len(), Rule::ALL.len());
+        assert!(rules.iter().any(|r| r["id"] == "self-loop"));
+
+        let results = run["results"].as_array().unwrap();
+        assert_eq!(results.len(), 2);
+        let result = &results[1];
+        assert_eq!(result["ruleId"], "self-loop");
+        assert_eq!(result["level"], "warning");
+        assert_eq!(
+            result["message"]["text"],
+            "`UI::Form` points at itself via `y`"
+        );
+        let location = &result["locations"][0]["physicalLocation"];
+        assert_eq!(location["artifactLocation"]["uri"], "model.fish");
+        assert_eq!(location["region"]["startLine"], 3);
+        // the span of the fish
+        assert_eq!(location["region"]["startColumn"], 10);
+        assert_eq!(location["region"]["endColumn"], 14);
+
+        // info is a `note` in SARIF
+        let mut info = report(LOOPS);
+        info.diagnostics[0].severity = Severity::Info;
+        assert_eq!(
+            render_sarif(&[info])["runs"][0]["results"][0]["level"],
+            "note"
+        );
+    }
+}
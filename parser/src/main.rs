@@ -1,14 +1,43 @@
 use arch::{
-    combineArcHs, convertToOriginalForm, markAsSingleChild, ArcH, Fish, OriginalArcHForm, Vertex,
+    combineArcHs, convertToOriginalForm, markAsSingleChild, ArcH, Fish, FishDirection,
+    OriginalArcHForm, Span, Vertex, VertexKind,
 };
 use serde::Serialize;
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::cell::RefCell;
 use std::error::Error;
 use std::fmt;
-extern crate regex;
-use regex::Regex;
 
+mod anchor;
 mod arch;
+mod attributes;
+mod comment;
+mod conditional;
+mod diagnostics;
+mod diff;
+mod expr;
+mod fence;
+mod graph;
+mod interpreter;
+mod lint;
+mod output;
+mod pattern;
+mod plugin;
+mod plugins;
+mod query;
+mod schema;
+mod set;
+mod template;
+#[cfg(test)]
+mod testing;
+mod validate;
+mod variables;
+#[cfg(feature = "wasm-plugins")]
+mod wasm;
+#[cfg(not(feature = "wasm-plugins"))]
+#[path = "wasm_disabled.rs"]
+mod wasm;
+mod watch;
 
 // Custom error type for parsing
 #[derive(Debug)]
@@ -18,6 +47,20 @@ enum ParseError {
     UnexpectedIndentation,
     MissingFish,
     InvalidSyntax(String),
+    InvalidExpression(expr::ExprError),
+    Plugin(plugin::PluginError),
+    Template(template::TemplateError),
+    Anchor(anchor::AnchorError),
+    UndefinedVariable(String, Option<Span>),
+    Condition(String, Option<Span>),
+    // a fenced block or a $( that is never closed
+    Unterminated(&'static str, Option<Span>),
+    // a malformed {key=value} block
+    Attributes(String, Option<Span>),
+    // a malformed {A, B} set
+    Set(String, Option<Span>),
+    // a reverse or bidirectional fish without a vertex after it
+    Fish(String, Option<Span>),
 }
 
 impl fmt::Display for ParseError {
@@ -28,10 +71,39 @@ impl fmt::Display for ParseError {
             ParseError::UnexpectedIndentation => write!(f, "Unexpected indentation"),
             ParseError::MissingFish => write!(f, "Missing fish operator (><)"),
             ParseError::InvalidSyntax(s) => write!(f, "Invalid syntax: {}", s),
+            ParseError::InvalidExpression(e) => write!(f, "Invalid EVAL expression: {}", e),
+            ParseError::Plugin(e) => write!(f, "{} (plugin {})", e.message, e.plugin),
+            ParseError::Template(e) => write!(f, "{}", e.message),
+            ParseError::Anchor(e) => write!(f, "{}", e.message),
+            ParseError::UndefinedVariable(name, _) => write!(f, "undefined variable `{}`", name),
+            ParseError::Condition(message, _) => write!(f, "{}", message),
+            ParseError::Unterminated(what, _) => write!(f, "{} is never closed", what),
+            ParseError::Attributes(message, _) => write!(f, "{}", message),
+            ParseError::Set(message, _) => write!(f, "{}", message),
+            ParseError::Fish(message, _) => write!(f, "{}", message),
         }
     }
 }
 
+impl ParseError {
+    fn to_diagnostic(&self) -> diagnostics::Diagnostic {
+        let (code, span) = match self {
+            ParseError::InvalidExpression(e) => ("syntax", e.span),
+            ParseError::Plugin(e) => ("plugin", e.span),
+            ParseError::Template(e) => ("template", e.span),
+            ParseError::Anchor(e) => ("anchor", e.span),
+            ParseError::UndefinedVariable(_, span) => ("variable", *span),
+            ParseError::Condition(_, span) => ("condition", *span),
+            ParseError::Unterminated(_, span) => ("syntax", *span),
+            ParseError::Attributes(_, span) => ("syntax", *span),
+            ParseError::Set(_, span) => ("syntax", *span),
+            ParseError::Fish(_, span) => ("syntax", *span),
+            _ => ("syntax", None),
+        };
+        diagnostics::Diagnostic::new(diagnostics::Severity::Error, code, self.to_string(), span)
+    }
+}
+
 macro_rules! println_ {
     ($($arg:tt)*) => {{
         // ----- uncomment to show logs at run time
@@ -41,64 +113,319 @@ macro_rules! println_ {
 
 impl Error for ParseError {}
 
-fn parse_inputs(input: &str) -> HashMap<&str, Vec<ArcH>> {
-    let mut files: HashMap<&str, Vec<&str>> = HashMap::new();
+// Settings for a parse, from the command line or from the caller
+#[derive(Debug, Clone, Default)]
+struct ParseOptions {
+    // --define NAME=VALUE, which wins over `let` in the source
+    defines: variables::Scope,
+    // --flag NAME, for #if blocks and [section if flag]
+    flags: conditional::Flags,
+}
+
+impl ParseOptions {
+    // Take `arg` if it is a parse option, with its value from `args`. The subcommands
+    // share these, so that they all parse a file the way the main command does.
+    fn take<S: AsRef<str>>(
+        &mut self,
+        arg: &str,
+        args: &mut impl Iterator<Item = S>,
+    ) -> Result<bool, Box<dyn Error>> {
+        match arg {
+            "--define" => {
+                let spec = args.next().ok_or("--define expects NAME=VALUE")?;
+                let (name, value) = variables::parse_override(spec.as_ref())?;
+                self.defines.insert(name, value);
+            }
+            "--flag" => {
+                let flag = args.next().ok_or("--flag expects a name")?;
+                self.flags.insert(flag.as_ref().to_string());
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+static DEFAULT_OPTIONS: ParseOptions = ParseOptions {
+    defines: variables::Scope::new(),
+    flags: conditional::Flags::new(),
+};
+
+// State shared by all parse functions while parsing one source text
+struct ParseContext<'a> {
+    // every line handed to the parser is a slice of this text
+    source: &'a str,
+    // directives, fish handlers and transforms contributed by plugins
+    plugins: &'a plugin::Registry,
+    options: &'a ParseOptions,
+    // the variables in scope; None where nothing is interpolated ([section] headers)
+    variables: Option<RefCell<variables::Scope>>,
+    // the `&name`d subtrees seen so far
+    anchors: RefCell<anchor::Anchors>,
+}
+
+impl<'a> ParseContext<'a> {
+    fn new(source: &'a str) -> ParseContext<'a> {
+        ParseContext {
+            source,
+            plugins: plugin::registry(),
+            options: &DEFAULT_OPTIONS,
+            variables: None,
+            anchors: RefCell::new(anchor::Anchors::new()),
+        }
+    }
+
+    fn with_options(source: &'a str, options: &'a ParseOptions) -> ParseContext<'a> {
+        ParseContext {
+            options,
+            variables: Some(RefCell::new(options.defines.clone())),
+            ..ParseContext::new(source)
+        }
+    }
+
+    fn span_of(&self, part: &str) -> Option<Span> {
+        Span::within(self.source, part)
+    }
+
+    // `&name` (a slice of the source) names `body`
+    fn anchor(&self, name: &str, body: &ArcH) -> Result<(), ParseError> {
+        let span = self.span_of(name);
+        let mut anchors = self.anchors.borrow_mut();
+        if let Some(first) = anchors.get(&name[1..]) {
+            let line = first.span.map(|s| s.line_col(self.source).0);
+            return Err(ParseError::Anchor(anchor::AnchorError {
+                message: match line {
+                    Some(line) => format!("anchor `{}` is already defined on line {}", name, line),
+                    None => format!("anchor `{}` is defined twice", name),
+                },
+                span,
+            }));
+        }
+        let body = body.clone();
+        anchors.insert(name[1..].to_string(), anchor::Anchor { body, span });
+        Ok(())
+    }
+
+    fn scope(&self) -> variables::Scope {
+        self.variables
+            .as_ref()
+            .map(|v| v.borrow().clone())
+            .unwrap_or_default()
+    }
+
+    fn set_scope(&self, scope: variables::Scope) {
+        if let Some(variables) = &self.variables {
+            *variables.borrow_mut() = scope;
+        }
+    }
+
+    // `let name = value`, unless --define already set it
+    fn define(&self, name: &str, value: &str) -> Result<(), ParseError> {
+        let value = self.interpolate(value)?.into_owned();
+        if let Some(variables) = &self.variables {
+            if !self.options.defines.contains_key(name) {
+                variables.borrow_mut().insert(name.to_string(), value);
+            }
+        }
+        Ok(())
+    }
+
+    // replace `${NAME}` in a slice of the source
+    fn interpolate<'t>(&self, text: &'t str) -> Result<Cow<'t, str>, ParseError> {
+        let variables = match &self.variables {
+            Some(variables) if text.contains("${") => variables.borrow(),
+            _ => return Ok(Cow::Borrowed(text)),
+        };
+        variables::interpolate(text, &variables)
+            .map(Cow::Owned)
+            .map_err(|(name, range)| {
+                ParseError::UndefinedVariable(name, self.span_of(&text[range]))
+            })
+    }
+
+    // the attributes of a `{key=value, ...}` block, if there is one
+    fn attributes(&self, block: Option<&str>) -> Result<attributes::Attributes, ParseError> {
+        let Some(block) = block else {
+            return Ok(attributes::Attributes::new());
+        };
+        attributes::parse(&self.interpolate(block)?)
+            .map_err(|message| ParseError::Attributes(message, self.span_of(block)))
+    }
+}
+
+// The lines of every section that is in effect, after fences, comments and #if blocks,
+// each with its name ("" for lines before the first header)
+fn split_sections<'a>(
+    input: &'a str,
+    options: &ParseOptions,
+) -> Result<Vec<(&'a str, Vec<&'a str>)>, ParseError> {
+    let mut files: Vec<(&str, Vec<&str>)> = Vec::new();
 
-    let mut lines: Vec<&str> = input.lines().collect();
+    let condition_error = |e: conditional::ConditionError| {
+        ParseError::Condition(e.message, Span::within(input, e.line))
+    };
+    // a fenced block is one line to everything below, so nothing inside it is a header,
+    // a comment or a directive
+    let lines = fence::join_blocks(input)
+        .map_err(|fence| ParseError::Unterminated("fenced block", Span::within(input, fence)))?;
+    let lines = comment::strip(lines)
+        .map_err(|open| ParseError::Unterminated("block comment", Span::within(input, open)))?;
+    // drop the lines of #if blocks that are not in effect
+    let mut lines: Vec<&str> =
+        conditional::select(lines, &options.flags).map_err(condition_error)?;
 
     let mut name = "";
+    // false in a [section if flag] whose flag is not set
+    let mut enabled = true;
     let mut linesCollected = Vec::new();
     let mut n = 0;
     while (n < lines.len()) {
         //     // check if starts/ends with []
         let line = lines[n].trim();
         if line.starts_with("[") && line.ends_with("]") {
-            if (!linesCollected.is_empty()) {
-                files.insert(name, linesCollected.clone());
+            if enabled {
+                files.push((name, linesCollected.clone()));
             }
-            name = line[1..line.len() - 1].trim();
+            (name, enabled) = conditional::section(line[1..line.len() - 1].trim(), &options.flags)
+                .map_err(condition_error)?;
             linesCollected = Vec::new();
         } else {
-            linesCollected.push(line);
+            linesCollected.push(lines[n]);
         }
         n = n + 1;
     }
 
-    if (!linesCollected.is_empty()) {
-        files.insert(name, linesCollected.clone());
+    if enabled {
+        files.push((name, linesCollected.clone()));
     }
+    return Ok(files);
+}
 
-    let mut finalResult: HashMap<&str, Vec<ArcH>> = HashMap::new();
+// Sections in source order, each with its name ("" for lines before the first header)
+fn parse_inputs_with<'a>(
+    input: &'a str,
+    options: &ParseOptions,
+) -> Result<Vec<(&'a str, Vec<ArcH>)>, ParseError> {
+    // the schema is declarations, not graph; see schema.rs
+    let files: Vec<(&str, Vec<&str>)> = split_sections(input, options)?
+        .into_iter()
+        .filter(|(name, lines)| !lines.is_empty() && *name != schema::SECTION)
+        .collect();
 
-    for (_key, values) in files.iter_mut() {
-        let parsed = parse_input(values.to_vec());
-        finalResult.insert(_key, parsed.unwrap());
+    let ctx = ParseContext::with_options(input, options);
+
+    // `let`s before the first [section] are visible in every section and template
+    if let Some(("", preamble)) = files.first() {
+        for line in preamble {
+            if let Some((name, value)) = variables::parse_definition(line) {
+                ctx.define(name, value)?;
+            }
+        }
     }
-    return finalResult;
+    let globals = ctx.scope();
+
+    // [template ...] sections are definitions, usable from any section
+    let mut templates = template::Templates::new();
+    let mut sections = Vec::new();
+    for (name, values) in files {
+        let header = match template::parse_header(name) {
+            Some(header) => header,
+            None => {
+                sections.push((name, values));
+                continue;
+            }
+        };
+        let error = |message| {
+            ParseError::Template(template::TemplateError {
+                message,
+                span: ctx.span_of(name),
+            })
+        };
+        let (template_name, params) = header.map_err(error)?;
+        if templates.contains_key(&template_name) {
+            return Err(error(format!(
+                "template `{}` is defined twice",
+                template_name
+            )));
+        }
+        // parameters stay as they are until the template is used
+        let mut scope = globals.clone();
+        for param in &params {
+            scope.insert(param.clone(), format!("${{{}}}", param));
+        }
+        ctx.set_scope(scope);
+        let body = parse_input(values, &ctx)?;
+        // a template's anchor would still have its `${param}`s in it
+        if let Some(anchor) = ctx.anchors.borrow().values().next() {
+            return Err(ParseError::Anchor(anchor::AnchorError {
+                message: "anchors cannot be defined in a template".to_string(),
+                span: anchor.span,
+            }));
+        }
+        templates.insert(template_name, template::Template { params, body });
+    }
+
+    // headers are vertices as written, without variables or anchors; collect_inputs
+    // parses them again to put them in front of their statements
+    let headers = ParseContext::new(input);
+    for (name, _) in sections.iter() {
+        parse_arch(&[name], 0, 0, &headers)?;
+    }
+
+    // every section is parsed before references are resolved, so an anchor may come later
+    let mut parsed_sections = Vec::new();
+    for (_key, values) in sections.iter() {
+        ctx.set_scope(globals.clone());
+        parsed_sections.push((*_key, parse_input(values.to_vec(), &ctx)?));
+    }
+    let anchors = ctx.anchors.take();
+    for template in templates.values_mut() {
+        template.body = anchor::resolve(std::mem::take(&mut template.body), &anchors)
+            .map_err(ParseError::Anchor)?;
+    }
+
+    let mut finalResult: Vec<(&str, Vec<ArcH>)> = Vec::new();
+    for (_key, parsed) in parsed_sections {
+        let parsed = anchor::resolve(parsed, &anchors).map_err(ParseError::Anchor)?;
+        let parsed = template::expand(parsed, &templates).map_err(ParseError::Template)?;
+        let parsed = ctx
+            .plugins
+            .transform_arches(_key, parsed)
+            .map_err(ParseError::Plugin)?;
+        finalResult.push((_key, parsed));
+    }
+    return Ok(finalResult);
     // return files;
 }
 
-fn collect_inputs(input: HashMap<&str, Vec<ArcH>>) -> Vec<Vec<OriginalArcHForm>> {
+// Flatten every section, keeping the section names and their order
+fn collect_inputs<'a>(
+    source: &str,
+    input: Vec<(&'a str, Vec<ArcH>)>,
+) -> Vec<(&'a str, Vec<OriginalArcHForm>)> {
+    let ctx = ParseContext::new(source);
     let mut collectedArcH = Vec::new();
     for (_key, values) in input.iter() {
         // key as lines
 
-        let prefix = parse_arch(&[_key], 0, 0);
+        let (prefix, _) = parse_arch(&[_key], 0, 0, &ctx)
+            .expect("parse_inputs_with reports the headers that do not parse");
 
-        let ar0 = convertToOriginalForm(None, prefix.unwrap().0);
+        let ar0 = convertToOriginalForm(None, prefix);
         //
         // concat map
-        let ars = values
-            .iter()
-            .map(|a| convertToOriginalForm(ar0.first().map(|ref_val| ref_val.clone()), a.clone()));
+        let ars = values.iter().flat_map(|a| {
+            convertToOriginalForm(ar0.first().map(|ref_val| ref_val.clone()), a.clone())
+        });
 
-        collectedArcH.extend(ars);
+        let forms = ctx.plugins.transform_forms(_key, ars.collect());
+        collectedArcH.push((*_key, forms));
     }
     return collectedArcH;
 }
 
 // Function to parse the entire input into an ArcH
-fn parse_input(lines_: Vec<&str>) -> Result<Vec<ArcH>, ParseError> {
+fn parse_input(lines_: Vec<&str>, ctx: &ParseContext) -> Result<Vec<ArcH>, ParseError> {
     let mut lines = lines_;
     let mut collectedArcH = Vec::new();
 
@@ -115,8 +442,11 @@ fn parse_input(lines_: Vec<&str>) -> Result<Vec<ArcH>, ParseError> {
         // check if the heading line starts with "##", if so, treat as comment and remove it
         if lines.len() > 0 && lines[0].trim().starts_with("##") {
             lines = lines[1..].to_vec();
+        } else if let Some((name, value)) = variables::parse_definition(lines[0]) {
+            ctx.define(name, value)?;
+            lines = lines[1..].to_vec();
         } else {
-            let (arch, consumed) = parse_arch(&lines, 0, 0)?;
+            let (arch, consumed) = parse_arch(&lines, 0, 0, ctx)?;
             collectedArcH.push(arch);
 
             lines = lines[consumed..].to_vec();
@@ -131,6 +461,7 @@ fn parse_arch(
     lines: &[&str],
     indent_level: usize,
     index: usize,
+    ctx: &ParseContext,
 ) -> Result<(ArcH, usize), ParseError> {
     let mut index_consumed = 0;
     if index >= lines.len() {
@@ -138,13 +469,28 @@ fn parse_arch(
     }
     let line = lines[index];
     if (line.trim().starts_with("EVAL:")) {
+        let expression = line.trim_start()[5..].trim();
+        // syntax errors are reported now, not when something runs the expression
+        let parsed = expr::parse(expression, ctx.span_of(expression))
+            .map_err(ParseError::InvalidExpression)?;
         return Ok((
             ArcH::EvalStatement {
-                expression: line[5..].trim().to_string(),
+                expression: expression.to_string(),
+                parsed: Some(parsed),
             },
             index + 1,
         ));
     }
+    if let Some((plugin, keyword, argument)) = ctx.plugins.directive_for(line) {
+        let arch = plugin.directive(keyword, argument).map_err(|message| {
+            ParseError::Plugin(plugin::PluginError {
+                plugin: plugin.name(),
+                message,
+                span: ctx.span_of(line.trim()),
+            })
+        })?;
+        return Ok((arch, index + 1));
+    }
 
     let line_indent = count_leading_spaces(line) / 2;
     println_!("Parsing Line {} ({}) ===> {}", index, indent_level, line);
@@ -187,7 +533,7 @@ fn parse_arch(
             allLines.as_slice(),
             next_index
         );
-        let (arcH, i) = parse_arch(allLines.as_slice(), 0, 0)?;
+        let (arcH, i) = parse_arch(allLines.as_slice(), 0, 0, ctx)?;
         if (isNewExp) {
             return Ok((arcH, index + i));
         } else {
@@ -195,60 +541,72 @@ fn parse_arch(
             return Ok((markAsSingleChild(arcH), index + i));
         }
     } else {
+        // `&name` names the subtree that starts here
+        let (anchor, trimmed_line) = match anchor::split(trimmed_line) {
+            Some((name, rest)) => (Some(name), rest),
+            None => (None, trimmed_line),
+        };
+        // `^` marks the vertex the indented block attaches to, if not the end of the chain
+        let (attach_here, trimmed_line) = match trimmed_line.strip_prefix('^') {
+            Some(rest) => (true, rest.trim_start()),
+            None => (false, trimmed_line),
+        };
         // Parse Vertex and Fish
-        let (vertex_str, after_first_vertex) = split_vertex_and_fish(trimmed_line)?;
+        let (vertex_str, after_first_vertex) = split_vertex_and_fish(trimmed_line, ctx)?;
         let mut fullArcH: ArcH;
+        // where the indented block goes, when `^` moved it off the end of the chain
+        let mut branch_point = None;
 
+        // one vertex, or the elements of a {set}
+        let vertices = parse_vertices(vertex_str, ctx)?;
         if (after_first_vertex.is_empty()) {
-            // in the case of ```\n, we take everything until \n```\n is met again
-            if (vertex_str == "```") {
-                // find the next occurrence of ```
-                let mut i = 1;
-                let mut newLine = "".to_string();
-                // log lines
-                println_!("lines: {:?}", lines);
-                while (i < lines.len()) {
-                    println_!("finding line closure... at {}", i);
-                    let line = lines[i].to_string();
-
-                    if (line.trim() == "```") {
-                        break;
-                    } else {
-                        newLine = format!("{}\n{}", newLine, line);
-                    }
-                    i = i + 1;
-                }
-                let vertex = parse_vertex(newLine.as_str())?;
-                println_!("Created Single mulit-line-str Vertex arCH: {:?}", vertex);
-                fullArcH = ArcH::Single {
-                    vertex,
-                    is_single_child: false,
-                };
-                return Ok((fullArcH, index + i));
+            println_!("Created Single Vertex arCH: {:?}", vertices);
+            fullArcH = fan_out(
+                vertex_str,
+                vertices
+                    .into_iter()
+                    .map(|vertex| ArcH::Single {
+                        vertex,
+                        is_single_child: false,
+                    })
+                    .collect(),
+                ctx,
+            );
+        } else {
+            let (fish, after_first_fish) = parse_fish(after_first_vertex, ctx)?;
+            let r = after_first_fish;
+            // after a `^`, the rest of the chain leaves the indented block to this level
+            let mut restOfLines = if attach_here {
+                vec![]
             } else {
-                let vertex = parse_vertex(vertex_str)?;
-                println_!("Created Single Vertex arCH: {:?}", vertex);
-                fullArcH = ArcH::Single {
-                    vertex,
+                lines[index + 1..].to_vec()
+            };
+            restOfLines.insert(0, r);
+            if attach_here {
+                let singles = vertices.iter().map(|vertex| ArcH::Single {
+                    vertex: vertex.clone(),
                     is_single_child: false,
-                }
+                });
+                branch_point = Some(fan_out(vertex_str, singles.collect(), ctx));
             }
-        } else {
-            let vertex = parse_vertex(vertex_str)?;
-            let (fish, after_first_fish) = parse_fish(after_first_vertex)?;
-            let r = after_first_fish.as_str();
-            let mut restOfLines = lines[index + 1..].to_vec();
-            restOfLines.insert(0, r);
             // now we parse r + the rest of the lines
-            let (arcH, s) = parse_arch(&restOfLines.as_slice(), indent_level, index)?;
-            println_!("Created Multi Vertex arCH: {:?}", vertex);
-            fullArcH = ArcH::ArcH {
-                vertex,
-                fish,
-                next: Box::new(arcH),
-                is_single_child: false,
-            };
-            index_consumed = s;
+            let (arcH, s) = parse_arch(&restOfLines.as_slice(), indent_level, 0, ctx)?;
+            println_!("Created Multi Vertex arCH: {:?}", vertices);
+            fullArcH = fan_out(
+                vertex_str,
+                vertices
+                    .into_iter()
+                    .map(|vertex| ArcH::ArcH {
+                        vertex,
+                        fish: fish.clone(),
+                        next: Box::new(arcH.clone()),
+                        is_single_child: false,
+                    })
+                    .collect(),
+                ctx,
+            );
+            // restOfLines[0] is the rest of this line, so s - 1 of the following lines were used
+            index_consumed = s - 1;
         }
 
         // Now, parse children if any
@@ -262,7 +620,7 @@ fn parse_arch(
                 break; // Done with current level
             } else if (child_indent == indent_level + 1) {
                 // Parse child
-                let (child_arch, consumed) = parse_arch(lines, child_indent, next_index)?;
+                let (child_arch, consumed) = parse_arch(lines, child_indent, next_index, ctx)?;
                 children.push(child_arch);
                 next_index = consumed;
             } else {
@@ -275,82 +633,279 @@ fn parse_arch(
         println_!(" ");
         println_!(">>>><<<< returning ^^ >>>><<<< ");
         println_!(" ");
-        if children.is_empty() {
+        let arch = if children.is_empty() {
             // println_!(">>>><<<< no children :) ");
             // println_!(" ");
-            Ok((fullArcH, next_index))
+            fullArcH
+        } else if let Some(branch_point) = branch_point {
+            // two ways on from the marked vertex: the rest of the chain, and the block
+            let branch = ArcH::ArcHWithNewLines {
+                prefix: Box::new(branch_point),
+                children,
+                is_single_child: false,
+            };
+            fan_out(vertex_str, vec![fullArcH, branch], ctx)
         } else {
-            Ok((
-                ArcH::ArcHWithNewLines {
-                    prefix: Box::new(fullArcH),
-                    children,
-                    is_single_child: false,
-                },
-                next_index,
-            ))
+            ArcH::ArcHWithNewLines {
+                prefix: Box::new(fullArcH),
+                children,
+                is_single_child: false,
+            }
+        };
+        if let Some(name) = anchor {
+            ctx.anchor(name, &arch)?;
         }
+        Ok((arch, next_index))
+    }
+}
+
+// A set fans out like `- ` children of an empty vertex; a single vertex stays as it is
+fn fan_out(vertex_str: &str, mut arches: Vec<ArcH>, ctx: &ParseContext) -> ArcH {
+    if arches.len() == 1 {
+        return arches.remove(0);
+    }
+    let mut placeholder = Vertex::placeholder();
+    placeholder.span = ctx.span_of(vertex_str);
+    ArcH::ArcHWithNewLines {
+        prefix: Box::new(ArcH::Single {
+            vertex: placeholder,
+            is_single_child: false,
+        }),
+        children: arches,
+        is_single_child: false,
     }
 }
 
+// The vertices of a {set}, nested sets flattened, or the one vertex of anything else
+// Attributes after a set go to each of its vertices, unless a vertex sets them itself.
+fn parse_vertices(s: &str, ctx: &ParseContext) -> Result<Vec<Vertex>, ParseError> {
+    let (trimmed, block) = attributes::split_vertex(s);
+    if !trimmed.starts_with('{') || set::end(trimmed) != Some(trimmed.len()) {
+        return Ok(vec![parse_vertex(s, ctx)?]);
+    }
+    let shared = ctx.attributes(block)?;
+    let mut vertices = Vec::new();
+    for element in set::elements(trimmed) {
+        for mut vertex in parse_vertices(element, ctx)? {
+            for (key, value) in &shared {
+                vertex
+                    .attributes
+                    .entry(key.clone())
+                    .or_insert_with(|| value.clone());
+            }
+            vertices.push(vertex);
+        }
+    }
+    if vertices.is_empty() {
+        return Err(ParseError::Set(
+            "Empty set {}".to_string(),
+            ctx.span_of(trimmed),
+        ));
+    }
+    Ok(vertices)
+}
+
 // Function to parse a vertex string into a Vertex
-fn parse_vertex(s: &str) -> Result<Vertex, ParseError> {
-    let parts: Vec<String> = s.split("::").map(|part| part.trim().to_string()).collect();
+fn parse_vertex(s: &str, ctx: &ParseContext) -> Result<Vertex, ParseError> {
+    let (body, block) = attributes::split_vertex(s);
+    let mut vertex = parse_vertex_body(body, ctx)?;
+    if let (true, Some(block)) = (vertex.is_placeholder(), block) {
+        return Err(ParseError::Attributes(
+            "a placeholder cannot have attributes".to_string(),
+            ctx.span_of(block),
+        ));
+    }
+    vertex.attributes = ctx.attributes(block)?;
+    vertex.span = ctx.span_of(s);
+    Ok(vertex)
+}
+
+// a vertex without its attributes
+fn parse_vertex_body(s: &str, ctx: &ParseContext) -> Result<Vertex, ParseError> {
+    let trimmed = s.trim();
+    // `_`, or nothing at all before a leading fish or after a trailing one
+    if trimmed.is_empty() || trimmed == "_" {
+        return Ok(Vertex::placeholder());
+    }
+    // fenced blocks, $(...) and "..." are taken whole, even when they contain `::`
+    if fence::split_block(trimmed).is_some() {
+        // code is kept as written: no ${} interpolation
+        let (language, text) = fence::content(trimmed);
+        return Ok(Vertex::with_kind(&text, VertexKind::Code, language));
+    }
+    if let Some(expression) = trimmed.strip_prefix("$(").and_then(|e| e.strip_suffix(')')) {
+        return Ok(Vertex::with_kind(
+            expression.trim(),
+            VertexKind::Expression,
+            None,
+        ));
+    }
+    if let Some(literal) = trimmed.strip_prefix('"').and_then(|l| l.strip_suffix('"')) {
+        return Ok(Vertex::with_kind(
+            &ctx.interpolate(literal)?,
+            VertexKind::Literal,
+            None,
+        ));
+    }
+    let text = ctx.interpolate(s)?;
+    let parts: Vec<String> = text
+        .split("::")
+        .map(|part| part.trim().to_string())
+        .collect();
     if parts.is_empty() {
         Err(ParseError::InvalidSyntax("Empty vertex".to_string()))
     } else {
-        Ok(Vertex(parts))
+        Ok(Vertex::new(parts))
     }
 }
 
-// Function to parse a fish string into a Fish
-fn parse_fish(s: &str) -> Result<(Fish, String), ParseError> {
-    let s = s.trim();
-
-    if !s.starts_with("><") {
-        return Err(ParseError::InvalidSyntax(
-            "Fish operator should start with '><'".to_string(),
-        ));
+// A fish operator at the start of `s`: ><label>, <label>< or <>label<>.
+// Returns its direction, its label and its length.
+fn fish_at(s: &str) -> Option<(FishDirection, &str, usize)> {
+    // the reverse and bidirectional labels cannot contain < or >, so `Vec<T>` is no fish
+    let plain = |label: &str| !label.contains(['<', '>']);
+    // the label may end in {attributes}, which may contain anything
+    if let Some(rest) = s.strip_prefix("><") {
+        let end = attributes::find(rest, ">")?;
+        return Some((FishDirection::Forward, &rest[..end], end + 3));
+    }
+    if let Some(rest) = s.strip_prefix("<>") {
+        let end = attributes::find(rest, "<>")?;
+        let (label, _) = attributes::split_label(&rest[..end]);
+        return plain(label).then(|| (FishDirection::Both, &rest[..end], end + 4));
     }
+    let rest = s.strip_prefix('<')?;
+    let end = attributes::find(rest, "><")?;
+    let (label, _) = attributes::split_label(&rest[..end]);
+    plain(label).then(|| (FishDirection::Reverse, &rest[..end], end + 3))
+}
 
-    // Remove the initial '><'
-    let rest = &s[2..];
+// Function to parse a fish string into a Fish
+fn parse_fish<'a>(s: &'a str, ctx: &ParseContext) -> Result<(Fish, &'a str), ParseError> {
+    let s = s.trim();
 
-    // Find the closing '>' character after '><'
-    if let Some(end_pos) = rest.find('>') {
-        // Extract the fish content between '><' and the next '>'
-        let fish_content = &rest[..end_pos];
-        // find the remaining content
-        let remaining = &rest[end_pos + 1..];
-        // log it
-        println_!("Remaining: {}", remaining);
+    match fish_at(s) {
+        Some((direction, fish_content, len)) => {
+            // find the remaining content
+            let remaining = &s[len..];
+            // log it
+            println_!("Remaining: {}", remaining);
 
-        Ok((
-            Fish(fish_content.trim().to_string()),
-            remaining.trim().to_string(),
-        ))
-    } else {
+            let (label, block) = attributes::split_label(fish_content);
+            let fish = Fish {
+                label: ctx.interpolate(label)?.trim().to_string(),
+                direction,
+                attributes: ctx.attributes(block)?,
+                span: ctx.span_of(&s[..len]),
+                call_site: None,
+            };
+            let fish = ctx.plugins.fish(fish).map_err(ParseError::Plugin)?;
+            Ok((fish, remaining.trim()))
+        }
         // No closing '>' found after '><'
-        Err(ParseError::InvalidSyntax(
-            "Fish operator missing closing '>'".to_string(),
-        ))
+        None if s.starts_with("><") => {
+            let span = ctx.span_of(s);
+            // a `##` in the label started a comment, which took the rest of the fish
+            let commented =
+                span.is_some_and(|span| ctx.source[span.end..].trim_start().starts_with("##"));
+            Err(ParseError::Fish(
+                format!(
+                    "`{}` is missing its closing `>`{}",
+                    s,
+                    if commented {
+                        " (`##` starts a comment)"
+                    } else {
+                        ""
+                    }
+                ),
+                span,
+            ))
+        }
+        None => Err(ParseError::Fish(
+            "Fish operator should start with '><', '<' or '<>'".to_string(),
+            ctx.span_of(s),
+        )),
     }
 }
 
 // Function to split a line into vertex and fish parts
-fn split_vertex_and_fish(s: &str) -> Result<(&str, &str), ParseError> {
-    // find $(...)
-    let re = Regex::new(r"\$\(.*?\)").unwrap();
-    match re.find(s) {
-        Some(pos) => {
-            let (vertex_str, remaining_str) = s.split_at(pos.end());
-            // remove the starting $( and closing )
-            let vertex_str = &vertex_str[2..vertex_str.len() - 1];
-            return Ok((vertex_str.trim(), remaining_str.trim()));
-        }
-        None => {}
+fn split_vertex_and_fish<'a>(
+    s: &'a str,
+    ctx: &ParseContext,
+) -> Result<(&'a str, &'a str), ParseError> {
+    let s = s.trim_start();
+    // a fenced block may contain anything, `><` included
+    let end = if let Some((block, _)) = fence::split_block(s) {
+        Some(block.len())
+    } else if s.starts_with('{') {
+        // sets end at their matching brace
+        Some(set::end(s).ok_or_else(|| ParseError::Unterminated("`{`", ctx.span_of(&s[..1])))?)
+    } else if s.starts_with("$(") {
+        // and so may $(...); parse_vertex takes it apart
+        Some(
+            expression_end(s)
+                .ok_or_else(|| ParseError::Unterminated("`$(`", ctx.span_of(&s[..2])))?,
+        )
+    } else {
+        None
+    };
+    if let Some(end) = end {
+        // with the {attributes} that follow, if any
+        let after = s[end..].trim_start();
+        let end = match after.strip_prefix('{') {
+            Some(_) => {
+                let block = set::end(after)
+                    .ok_or_else(|| ParseError::Unterminated("`{`", ctx.span_of(&after[..1])))?;
+                s.len() - after.len() + block
+            }
+            None => end,
+        };
+        let (vertex_str, remaining_str) = s.split_at(end);
+        return Ok((vertex_str, remaining_str.trim()));
     }
 
-    match s.find("><") {
+    // the vertex ends where the first fish starts, outside its {attributes};
+    // a stray `><` is left to parse_fish to report
+    let mut start = None;
+    let mut i = 0;
+    while i < s.len() {
+        let rest = &s[i..];
+        // nor does a "literal" hold a fish
+        if rest.starts_with('"') {
+            i += attributes::group_len(rest).unwrap_or(rest.len());
+            continue;
+        }
+        if rest.starts_with('{') {
+            i += set::end(rest)
+                .ok_or_else(|| ParseError::Unterminated("`{`", ctx.span_of(&rest[..1])))?;
+            continue;
+        }
+        if let Some((direction, _, len)) = fish_at(rest) {
+            if direction.is_forward() {
+                start = Some(i);
+                break;
+            }
+            // `<label><` and `<>label<>` start a token, so `Vec<T>` is still a name
+            let boundary = s[..i]
+                .chars()
+                .next_back()
+                .is_none_or(|c| c.is_whitespace() || matches!(c, '}' | ')' | '"'));
+            if boundary {
+                let fish = &rest[..len];
+                let (next, _) = split_vertex_and_fish(&rest[len..], ctx)?;
+                if !balanced_angles(next) {
+                    return Err(ParseError::Fish(
+                        format!("`{}` must be followed by a vertex, not `{}`", fish, next),
+                        ctx.span_of(fish),
+                    ));
+                }
+                start = Some(i);
+                break;
+            }
+        }
+        i += rest.chars().next().map_or(1, char::len_utf8);
+    }
+    match start.or_else(|| attributes::find(s, "><")) {
         Some(pos) => {
             let (vertex_str, remaining_str) = s.split_at(pos);
             Ok((vertex_str.trim(), remaining_str.trim()))
@@ -359,11 +914,82 @@ fn split_vertex_and_fish(s: &str) -> Result<(&str, &str), ParseError> {
     }
 }
 
+// Whether the `<` and `>` of a vertex pair up, as in Vec<T>. In `x> R` they do not: that is
+// what is left when a `<` earlier on the line was taken for a fish.
+fn balanced_angles(vertex: &str) -> bool {
+    let mut depth = 0;
+    let mut i = 0;
+    while i < vertex.len() {
+        let rest = &vertex[i..];
+        if let Some(len) = attributes::group_len(rest) {
+            i += len;
+            continue;
+        }
+        match rest.chars().next() {
+            Some('<') => depth += 1,
+            Some('>') if depth == 0 => return false,
+            Some('>') => depth -= 1,
+            _ => {}
+        }
+        i += rest.chars().next().map_or(1, char::len_utf8);
+    }
+    depth == 0
+}
+
+// Where the `$(` at the start of `s` is closed: the byte after its `)`. Parentheses nest;
+// inside '...' or "..." they do not count, and a backslash escapes the next character.
+fn expression_end(s: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut quote = None;
+    let mut chars = s.char_indices().skip(1);
+    while let Some((i, c)) = chars.next() {
+        match (quote, c) {
+            (_, '\\') => {
+                chars.next();
+            }
+            (Some(q), _) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i + 1);
+                }
+            }
+            (None, _) => {}
+        }
+    }
+    None
+}
+
 // Function to count leading spaces (each indent level is 2 spaces)
 fn count_leading_spaces(s: &str) -> usize {
     s.chars().take_while(|c| *c == ' ').count()
 }
 
+const USAGE: &str = "usage: rust-parser [--validate] [--watch] [--rule RULE=SEVERITY]... [--define NAME=VALUE]... [--flag NAME]... [--plugin FILE]... (<file> | --from-json <file>)";
+
+// print how to call a command and stop, as for any command-line mistake
+fn usage(text: &str) -> ! {
+    eprintln!("{}", text);
+    std::process::exit(2)
+}
+
+// Read a .fish file for parsing
+fn read_source(path: &str) -> std::io::Result<String> {
+    let fileContent = std::fs::read_to_string(path)?;
+    // (10/FEB) hot fix only:
+    // to-do: fix a bug that causes error if last line is not ##
+    Ok(fileContent + "\n\n\n##")
+}
+
+// write result as JONS to file, see output.rs for the layout
+fn write_output(document: &output::Document) {
+    let json = serde_json::to_string_pretty(document).unwrap();
+    std::fs::write("output.json", json).unwrap();
+}
+
 // Example usage
 fn main() -> Result<(), Box<dyn Error>> {
     // // example inputs
@@ -373,39 +999,400 @@ fn main() -> Result<(), Box<dyn Error>> {
     // let input = r#"UI::App ><renders> a ><go> b"#;
     // ----------------
 
-    // get first arg as path ::
-    let path = std::env::args().nth(1);
-
-    // read from file
-    let mut fileContent = std::fs::read_to_string(path.unwrap());
-
-    let input = match fileContent {
-        // (10/FEB) hot fix only:
-        // to-do: fix a bug that causes error if last line is not ##
-        Ok(fileContent) => fileContent + "\n\n\n##",
-        Err(e) => {
-            println_!("Error reading file: {}", e);
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(|a| a.as_str()) {
+        Some("lint") => return lint::run(&args[1..]),
+        Some("eval") => return interpreter::run(&args[1..]),
+        Some("query") => return query::run(&args[1..]),
+        Some("diff") => return diff::run(&args[1..]),
+        Some("plugins") => {
+            // the plugins compiled into this build
+            for name in plugin::registry().names() {
+                println!("{}", name);
+            }
             return Ok(());
         }
+        _ => {}
+    }
+
+    // get args: [--validate] [--watch] [--rule RULE=SEVERITY]... [--define NAME=VALUE]... [--flag NAME]... [--plugin FILE]... (path | --from-json path)
+    let mut path = None;
+    let mut plugin_paths = Vec::new();
+    let mut limits = wasm::Limits::default();
+    let mut from_json = false;
+    let mut watch = false;
+    let mut should_validate = false;
+    let mut rules = validate::RuleConfig::default();
+    let mut options = ParseOptions::default();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if options.take(&arg, &mut args)? {
+            continue;
+        }
+        match arg.as_str() {
+            "--validate" => should_validate = true,
+            "--watch" => watch = true,
+            "--rule" => {
+                let spec = args.next().ok_or("--rule expects RULE=SEVERITY")?;
+                rules.apply_override(&spec)?;
+                should_validate = true;
+            }
+            "--from-json" => {
+                path = Some(args.next().ok_or("--from-json expects a file")?);
+                from_json = true;
+            }
+            "--plugin" => plugin_paths.push(args.next().ok_or("--plugin expects a file")?),
+            "--plugin-fuel" => {
+                let fuel = args.next().ok_or("--plugin-fuel expects a number")?;
+                limits.fuel = fuel.parse().map_err(|_| "--plugin-fuel expects a number")?;
+            }
+            "--plugin-memory" => {
+                let mib = args.next().ok_or("--plugin-memory expects MiB")?;
+                let mib: usize = mib.parse().map_err(|_| "--plugin-memory expects MiB")?;
+                limits.memory = mib << 20;
+            }
+            _ => path = Some(arg),
+        }
+    }
+    let path = path.unwrap_or_else(|| usage(USAGE));
+    let mut job = Job {
+        plugins: wasm::Host::load(&plugin_paths, limits)?,
+        plugin_paths,
+        limits,
+        path,
+        from_json,
+        options,
+        should_validate,
+        rules,
     };
 
-    // let input = r#"UI::App"#;
+    if !watch {
+        if !job.run() {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    // keep going after errors: they are reported, and the next save may fix them
+    job.run();
+    let mut files = vec![job.path.clone()];
+    files.extend(job.plugin_paths.clone());
+    watch::watch(&files, || {
+        // the change may be to a plugin, so they are loaded again
+        if let Err(e) = job.reload_plugins() {
+            eprintln!("{}", e);
+            return;
+        }
+        if job.run() {
+            eprintln!("wrote output.json");
+        }
+    })?;
+    Ok(())
+}
+
+// What the command line asked for, so that --watch can do it again
+struct Job {
+    path: String,
+    from_json: bool,
+    options: ParseOptions,
+    plugins: wasm::Host,
+    // where `plugins` came from, to load them again after a change
+    plugin_paths: Vec<String>,
+    limits: wasm::Limits,
+    should_validate: bool,
+    rules: validate::RuleConfig,
+}
+
+impl Job {
+    fn reload_plugins(&mut self) -> Result<(), String> {
+        self.plugins = wasm::Host::load(&self.plugin_paths, self.limits)?;
+        Ok(())
+    }
+
+    // false if errors were reported
+    fn run(&self) -> bool {
+        let path = &self.path;
+        if self.from_json {
+            // a model saved earlier, or generated by a tool: there is no source text,
+            // so only the model rules run and diagnostics carry no positions
+            let document = std::fs::read_to_string(path)
+                .map_err(|e| e.to_string())
+                .and_then(|text| output::Document::from_json(&text));
+            let document = match document {
+                Ok(document) => document,
+                Err(e) => {
+                    eprintln!("{}: {}", path, e);
+                    return false;
+                }
+            };
+            let mut sections: Vec<(&str, Vec<OriginalArcHForm>)> = document
+                .sections
+                .iter()
+                .map(|s| (s.name.as_str(), s.forms.clone()))
+                .collect();
+            if diagnostics::report(&self.plugins.apply_forms(&mut sections), path, "") {
+                return false;
+            }
+            // checked before the sections go into the document, reported after it is written
+            let found = if self.should_validate {
+                validate::validate(&sections, &self.rules)
+            } else {
+                vec![]
+            };
+            write_output(&output::Document::new(sections));
+            return !diagnostics::report(&found, path, "");
+        }
+
+        // read from file
+        let input = match read_source(path) {
+            Ok(input) => input,
+            Err(e) => {
+                eprintln!("{}: {}", path, e);
+                return false;
+            }
+        };
+
+        // let input = r#"UI::App"#;
+
+        let mut parsed = match parse_inputs_with(input.as_str(), &self.options) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                diagnostics::report(&[e.to_diagnostic()], path, &input);
+                return false;
+            }
+        };
+        // WebAssembly plugins: an error from any of them stops here, before output.json is written
+        let mut found = self.plugins.apply_arches(&mut parsed);
+        let mut oringalForms = collect_inputs(input.as_str(), parsed);
+        found.extend(self.plugins.apply_forms(&mut oringalForms));
+        if diagnostics::report(&found, path, &input) {
+            return false;
+        }
+        println_!("{:#?}", oringalForms);
+
+        for (_name, of) in oringalForms.iter() {
+            for o in of {
+                println_!("\n\n{}\n\n", o);
+            }
+        }
 
-    let oringalForms = collect_inputs(parse_inputs(input.as_str()));
-    println_!("{:#?}", oringalForms);
+        write_output(&output::Document::new(oringalForms));
 
-    // concat
-    let mut allOriginalForms = Vec::new();
-    for of in oringalForms {
-        allOriginalForms.extend(of.clone());
-        for o in of {
-            println_!("\n\n{}\n\n", o);
+        if self.should_validate {
+            let found = lint::lint_source(&input, &self.options, &self.rules);
+            if diagnostics::report(&found, path, &input) {
+                return false;
+            }
         }
+        true
     }
+}
 
-    // write result as JONS to file
-    let json = serde_json::to_string_pretty(&allOriginalForms).unwrap();
-    std::fs::write("output.json", json).unwrap();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{error, written};
 
-    Ok(())
+    #[test]
+    fn a_file_that_cannot_be_read_fails_the_run() {
+        let job = Job {
+            path: "does-not-exist.fish".to_string(),
+            from_json: false,
+            options: ParseOptions::default(),
+            plugins: wasm::Host::load(&[], wasm::Limits::default()).unwrap(),
+            plugin_paths: vec![],
+            limits: wasm::Limits::default(),
+            should_validate: false,
+            rules: validate::RuleConfig::default(),
+        };
+        assert!(!job.run());
+    }
+
+    #[test]
+    fn section_headers_that_do_not_parse_are_errors() {
+        assert_eq!(
+            error("[{A, B]\nX ><y> Z\n"),
+            ("`{` is never closed".to_string(), Some((1, 2)))
+        );
+        assert_eq!(
+            error("[UI::App{x=1, x=1}]\n><y> Z\n"),
+            ("attribute `x` is set twice".to_string(), Some((1, 9)))
+        );
+    }
+
+    #[test]
+    fn an_attribute_set_twice_is_an_error() {
+        assert_eq!(
+            error("[UI::App]\n><x> A{x=1, x=2}\n"),
+            ("attribute `x` is set twice".to_string(), Some((2, 7)))
+        );
+        assert_eq!(
+            error("A ><x{async, async}> B\n"),
+            ("attribute `async` is set twice".to_string(), Some((1, 6)))
+        );
+    }
+
+    #[test]
+    fn an_empty_set_points_at_its_braces() {
+        assert_eq!(
+            error("[UI::App]\n><y> {}\n"),
+            ("Empty set {}".to_string(), Some((2, 6)))
+        );
+    }
+
+    #[test]
+    fn reverse_and_bidirectional_fish() {
+        assert_eq!(written("A <x>< B ><y> C\n"), ["A <x>< B ><y> C"]);
+        assert_eq!(written("A <>x<> B\n"), ["A <>x<> B"]);
+        // not at the start of a word, so part of the name
+        assert_eq!(
+            written("A ><has> Vec<T> <x>< B\n"),
+            ["A ><has> Vec<T> <x>< B"]
+        );
+    }
+
+    #[test]
+    fn a_reverse_fish_must_be_followed_by_a_vertex() {
+        assert_eq!(
+            error("P < Q ><x> R\n"),
+            (
+                "`< Q ><` must be followed by a vertex, not `x> R`".to_string(),
+                Some((1, 3))
+            )
+        );
+    }
+
+    #[test]
+    fn indented_lines_keep_their_indentation() {
+        assert_eq!(written("A ><x>\n  - B\n  - C\n"), ["A ><x> B", "A ><x> C"]);
+    }
+
+    #[test]
+    fn the_line_after_a_chain_is_not_skipped() {
+        assert_eq!(written("A ><x> B\nC ><y> D\n"), ["A ><x> B", "C ><y> D"]);
+    }
+
+    #[test]
+    fn every_dash_child_of_a_chain_is_kept() {
+        assert_eq!(
+            written("A ><x> B ><y>\n  - C\n  - D\n"),
+            ["A ><x> B ><y> C", "A ><x> B ><y> D"]
+        );
+    }
+
+    #[test]
+    fn a_chain_with_only_children_that_go_on_from_it() {
+        assert_eq!(written("A ><x> B\n  C\n"), ["A ><x> B ><> C"]);
+        assert_eq!(written("A ><x>\n  C\n  D\n"), ["A ><x> C ><> D"]);
+    }
+
+    #[test]
+    fn a_child_that_goes_on_after_dash_children() {
+        assert_eq!(written("A ><x> B\n  - C\n  D\n"), ["A ><x> B ><> C ><> D"]);
+    }
+
+    #[test]
+    fn literals_hold_fish_and_braces() {
+        assert_eq!(written("A ><x> \"p ><q> r\"\n"), ["A ><x> \"p ><q> r\""]);
+        assert_eq!(
+            written("\"a ><b> c\" ><x> B <y>< \"d <e>< f\"\n"),
+            ["\"a ><b> c\" ><x> B <y>< \"d <e>< f\""]
+        );
+        assert_eq!(written("A ><x> \"a { b\"\n"), ["A ><x> \"a { b\""]);
+        assert_eq!(
+            written("A ><x> \"a } b\" ><y> C\n"),
+            ["A ><x> \"a } b\" ><y> C"]
+        );
+    }
+
+    #[test]
+    fn a_caret_moves_the_indented_block_up_the_chain() {
+        assert_eq!(
+            written("A ><x> ^B ><y> C\n  - ><z> D\n  - ><w> E\n"),
+            ["A ><x> B ><y> C", "A ><x> B ><z> D", "A ><x> B ><w> E"]
+        );
+        assert_eq!(written("^A ><x> B\n  - ><y> C\n"), ["A ><x> B", "A ><y> C"]);
+        // at a set, the block goes on from each of its vertices
+        assert_eq!(
+            written("A ><x> ^{B, C} ><y> D\n  - ><z> E\n"),
+            [
+                "A ><x> B ><y> D",
+                "A ><x> C ><y> D",
+                "A ><x> B ><z> E",
+                "A ><x> C ><z> E"
+            ]
+        );
+        // the first `^` counts
+        assert_eq!(
+            written("A ><x> ^B ><y> ^C ><z> D\n  - ><w> E\n"),
+            ["A ><x> B ><y> C ><z> D", "A ><x> B ><w> E"]
+        );
+        // with no block, it changes nothing
+        assert_eq!(written("A ><x> ^B ><y> C\n"), ["A ><x> B ><y> C"]);
+    }
+
+    #[test]
+    fn a_fish_cut_short_points_at_itself() {
+        assert_eq!(
+            error("[UI::App]\nA ><x ## y> B\n"),
+            (
+                "`><x` is missing its closing `>` (`##` starts a comment)".to_string(),
+                Some((2, 3))
+            )
+        );
+        assert_eq!(
+            error("[UI::App]\nA ><x\n"),
+            ("`><x` is missing its closing `>`".to_string(), Some((2, 3)))
+        );
+    }
+
+    #[test]
+    fn an_eval_under_a_chain_is_a_form_of_its_own() {
+        let forms = crate::testing::forms("A ><x> B\n  EVAL: assert(false)\n  - C\n");
+        let evals: Vec<_> = forms.iter().filter(|f| f.is_eval()).collect();
+        assert_eq!(evals.len(), 1);
+        assert!(evals[0].vf_pairs.is_empty());
+        assert_eq!(evals[0].executable_expression, "assert(false)");
+        assert!(evals[0].expression.is_some());
+        // and adds no edge
+        let document = output::Document::new(vec![("", forms)]);
+        assert_eq!(document.edges.len(), 2);
+        // nor is one under a `- ` child joined onto it
+        let nested = crate::testing::forms("A ><x> B\n  - C\n    EVAL: 1 == 1\n");
+        assert_eq!(nested.iter().filter(|f| f.is_eval()).count(), 1);
+        assert_eq!(nested.len(), 2);
+        assert_eq!(
+            written("A ><x> B\n  EVAL: assert(false)\n  - C\n")
+                .into_iter()
+                .filter(|f| !f.is_empty())
+                .collect::<Vec<_>>(),
+            ["A ><x> B ><> C"]
+        );
+    }
+
+    #[test]
+    fn expressions_end_at_their_matching_parenthesis() {
+        fn end(s: &str) -> Option<&str> {
+            expression_end(s).map(|i| &s[..i])
+        }
+        assert_eq!(end("$(a) ><x> B"), Some("$(a)"));
+        assert_eq!(end("$(f(g(1), (2))) ><x> B"), Some("$(f(g(1), (2)))"));
+        // quoted and escaped parentheses do not count
+        assert_eq!(end(r#"$(f(")") + ')') x"#), Some(r#"$(f(")") + ')')"#));
+        assert_eq!(end(r#"$("it's (") x"#), Some(r#"$("it's (")"#));
+        assert_eq!(end(r"$(a \) b) x"), Some(r"$(a \) b)"));
+        assert_eq!(end("$(f(1)"), None);
+        assert_eq!(end("$(\")"), None);
+    }
+
+    #[test]
+    fn an_expression_vertex_can_hold_fish_and_parentheses() {
+        assert_eq!(
+            written("$(count(A ><x>) > (1)) ><feeds> B\n"),
+            ["$(count(A ><x>) > (1)) ><feeds> B"]
+        );
+        assert_eq!(
+            error("A ><x> $(f(\")\"\n"),
+            ("`$(` is never closed".to_string(), Some((1, 8)))
+        );
+    }
 }
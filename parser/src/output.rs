@@ -0,0 +1,136 @@
+use crate::arch::{convertToOriginalForm, ArcH, Fish, FishDirection, OriginalArcHForm, Vertex};
+use crate::attributes;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// Version of the output.json layout, see schemas/output-v1.schema.json.
+// Bump it (and add a new schema file) for any change that is not purely additive.
+pub const VERSION: u32 = 1;
+
+// The document written to output.json
+#[derive(Serialize, Deserialize)]
+pub struct Document {
+    pub version: u32,
+    // the flattened forms, grouped by the [section] they were written in
+    pub sections: Vec<Section>,
+    // every distinct edge of the graph, in the order they first appear; vertices and fish
+    // carry the attributes of all the places they are written (later ones win)
+    pub edges: Vec<Edge>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Section {
+    // "" for the statements before the first [section] header
+    pub name: String,
+    pub forms: Vec<OriginalArcHForm>,
+}
+
+#[derive(Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Edge {
+    pub from: Vertex,
+    pub fish: Fish,
+    pub to: Vertex,
+}
+
+impl Document {
+    pub fn new<S: Into<String>>(sections: Vec<(S, Vec<OriginalArcHForm>)>) -> Document {
+        let vertex_attributes =
+            attributes::of_vertices(sections.iter().flat_map(|(_, forms)| forms));
+        let with_attributes = |v: &Vertex| Vertex {
+            attributes: vertex_attributes[v].clone(),
+            ..v.clone()
+        };
+        // edge -> its index in `edges`
+        let mut seen: HashMap<Edge, usize> = HashMap::new();
+        let mut edges: Vec<Edge> = Vec::new();
+        for (_, forms) in sections.iter() {
+            for form in forms {
+                for (from, fish, to) in form.edges() {
+                    // a placeholder stands for a vertex that was left out
+                    if from.is_placeholder() || to.is_placeholder() {
+                        continue;
+                    }
+                    let edge = Edge {
+                        from: with_attributes(from),
+                        // edges() already pointed it the right way
+                        fish: Fish {
+                            direction: FishDirection::Forward,
+                            ..fish.clone()
+                        },
+                        to: with_attributes(to),
+                    };
+                    match seen.get(&edge) {
+                        Some(&i) => edges[i].fish.attributes.extend(edge.fish.attributes),
+                        None => {
+                            seen.insert(edge.clone(), edges.len());
+                            edges.push(edge);
+                        }
+                    }
+                }
+            }
+        }
+        Document {
+            version: VERSION,
+            sections: sections
+                .into_iter()
+                .map(|(name, forms)| Section {
+                    name: name.into(),
+                    forms,
+                })
+                // comments before the first header are not worth a section
+                .filter(|s| !s.name.is_empty() || !s.forms.is_empty())
+                .collect(),
+            edges,
+        }
+    }
+
+    // Read a model back from JSON. Accepts an output.json document, a bare list of
+    // flattened forms, or a list of ArcH trees (which are flattened here).
+    pub fn from_json(text: &str) -> Result<Document, String> {
+        let value: serde_json::Value = serde_json::from_str(text).map_err(|e| e.to_string())?;
+        if value.is_object() {
+            let document: Document = serde_json::from_value(value).map_err(|e| e.to_string())?;
+            if document.version != VERSION {
+                return Err(format!(
+                    "unsupported output version {} (expected {})",
+                    document.version, VERSION
+                ));
+            }
+            // edges are derived data, so rebuild them rather than trusting the file
+            let sections = document
+                .sections
+                .into_iter()
+                .map(|s| (s.name, s.forms))
+                .collect();
+            return Ok(Document::new(sections));
+        }
+        if let Ok(forms) = serde_json::from_value::<Vec<OriginalArcHForm>>(value.clone()) {
+            return Ok(Document::new(vec![("", forms)]));
+        }
+        let mut trees: Vec<ArcH> = serde_json::from_value(value)
+            .map_err(|e| format!("expected an output document, forms or ArcH trees: {}", e))?;
+        // trees written before placeholders had a kind: a lone empty segment is one
+        for tree in &mut trees {
+            tree.for_each_mut(
+                &mut |v| {
+                    if v.kind.is_path() && v.segments == [""] {
+                        *v = Vertex {
+                            span: v.span,
+                            ..Vertex::placeholder()
+                        };
+                    }
+                },
+                &mut |_| {},
+            );
+        }
+        let forms = trees
+            .into_iter()
+            .flat_map(|arch| convertToOriginalForm(None, arch))
+            .collect();
+        Ok(Document::new(vec![("", forms)]))
+    }
+
+    pub fn forms(&self) -> Vec<OriginalArcHForm> {
+        self.sections.iter().flat_map(|s| s.forms.clone()).collect()
+    }
+}
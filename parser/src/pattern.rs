@@ -0,0 +1,54 @@
+use crate::arch::Vertex;
+use std::fmt;
+
+// A vertex path with wildcards, e.g. UI::* or DB::**::Users; it only matches paths
+//   `*`  stands for exactly one segment
+//   `**` stands for any number of segments (including none)
+//   inside a segment, `*` matches any text, e.g. UI::Add*
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VertexPattern(pub Vec<String>);
+
+impl VertexPattern {
+    pub fn parse(s: &str) -> VertexPattern {
+        VertexPattern(s.split("::").map(|part| part.trim().to_string()).collect())
+    }
+
+    pub fn matches(&self, vertex: &Vertex) -> bool {
+        vertex.kind.is_path() && match_segments(&self.0, &vertex.segments)
+    }
+}
+
+impl fmt::Display for VertexPattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0.join("::"))
+    }
+}
+
+fn match_segments(pattern: &[String], segments: &[String]) -> bool {
+    match pattern.split_first() {
+        None => segments.is_empty(),
+        Some((p, rest)) if p == "**" => {
+            (0..=segments.len()).any(|skip| match_segments(rest, &segments[skip..]))
+        }
+        Some((p, rest)) => match segments.split_first() {
+            Some((s, tail)) => glob(p, s) && match_segments(rest, tail),
+            None => false,
+        },
+    }
+}
+
+// glob match of one segment (or a fish label), where `*` matches any run of characters
+pub fn glob(pattern: &str, text: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == text,
+        Some((head, tail)) => {
+            let text = match text.strip_prefix(head) {
+                Some(text) => text,
+                None => return false,
+            };
+            (0..=text.len())
+                .filter(|i| text.is_char_boundary(*i))
+                .any(|i| glob(tail, &text[i..]))
+        }
+    }
+}
@@ -0,0 +1,260 @@
+use crate::arch::{ArcH, Fish, OriginalArcHForm, Span};
+use crate::pattern::glob;
+use std::sync::OnceLock;
+
+// Extends the parser without touching parse_arch. Every hook has a default that does
+// nothing, so a plugin only implements what it needs:
+//
+//   directives   lines starting with one of `directives()` (e.g. "ROUTE:") are handed to
+//                `directive` instead of being parsed as vertices and fish
+//   fish labels  every fish whose label globs one of `fish_labels()` (e.g. "http:*") goes
+//                through `fish`, which may rewrite or reject it
+//   transforms   `transform_arches` sees each section after parsing, `transform_forms`
+//                after flattening
+//
+// Plugins are compiled in: add a module under src/plugins/ and list it in plugins::registered().
+pub trait Plugin: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn directives(&self) -> &[&'static str] {
+        &[]
+    }
+
+    // `argument` is the rest of the line after the keyword, trimmed
+    fn directive(&self, keyword: &str, _argument: &str) -> Result<ArcH, String> {
+        Err(format!("`{}` is not handled by {}", keyword, self.name()))
+    }
+
+    fn fish_labels(&self) -> &[&'static str] {
+        &[]
+    }
+
+    fn fish(&self, fish: Fish) -> Result<Fish, String> {
+        Ok(fish)
+    }
+
+    fn transform_arches(&self, _section: &str, arches: Vec<ArcH>) -> Result<Vec<ArcH>, String> {
+        Ok(arches)
+    }
+
+    fn transform_forms(
+        &self,
+        _section: &str,
+        forms: Vec<OriginalArcHForm>,
+    ) -> Vec<OriginalArcHForm> {
+        forms
+    }
+}
+
+// An error raised by a plugin, with the plugin's name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PluginError {
+    pub plugin: &'static str,
+    pub message: String,
+    pub span: Option<Span>,
+}
+
+// The plugins in effect for a parse, consulted in registration order
+#[derive(Default)]
+pub struct Registry {
+    plugins: Vec<Box<dyn Plugin>>,
+}
+
+impl Registry {
+    pub fn new() -> Registry {
+        Registry::default()
+    }
+
+    pub fn register(&mut self, plugin: Box<dyn Plugin>) {
+        self.plugins.push(plugin);
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.plugins.iter().map(|p| p.name()).collect()
+    }
+
+    // the plugin and keyword claiming a line, if any; the first registered plugin wins
+    pub fn directive_for<'l>(&self, line: &'l str) -> Option<(&dyn Plugin, &'static str, &'l str)> {
+        let line = line.trim_start();
+        self.plugins.iter().find_map(|p| {
+            p.directives().iter().find_map(|keyword| {
+                let argument = line.strip_prefix(keyword)?;
+                Some((p.as_ref(), *keyword, argument.trim()))
+            })
+        })
+    }
+
+    // run a fish through every plugin claiming its label
+    pub fn fish(&self, mut fish: Fish) -> Result<Fish, PluginError> {
+        for p in &self.plugins {
+            if p.fish_labels().iter().any(|l| glob(l, &fish.label)) {
+                let span = fish.span;
+                fish = p.fish(fish).map_err(|message| PluginError {
+                    plugin: p.name(),
+                    message,
+                    span,
+                })?;
+            }
+        }
+        Ok(fish)
+    }
+
+    pub fn transform_arches(
+        &self,
+        section: &str,
+        mut arches: Vec<ArcH>,
+    ) -> Result<Vec<ArcH>, PluginError> {
+        for p in &self.plugins {
+            arches = p
+                .transform_arches(section, arches)
+                .map_err(|message| PluginError {
+                    plugin: p.name(),
+                    message,
+                    span: None,
+                })?;
+        }
+        Ok(arches)
+    }
+
+    pub fn transform_forms(
+        &self,
+        section: &str,
+        mut forms: Vec<OriginalArcHForm>,
+    ) -> Vec<OriginalArcHForm> {
+        for p in &self.plugins {
+            forms = p.transform_forms(section, forms);
+        }
+        forms
+    }
+}
+
+// The compiled-in plugins, used by every parse
+pub fn registry() -> &'static Registry {
+    static REGISTRY: OnceLock<Registry> = OnceLock::new();
+    REGISTRY.get_or_init(|| {
+        let mut registry = Registry::new();
+        for plugin in crate::plugins::registered() {
+            registry.register(plugin);
+        }
+        registry
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::Vertex;
+
+    // appends its name to every fish label and every section, so the order shows
+    struct Tag(&'static str);
+
+    impl Plugin for Tag {
+        fn name(&self) -> &'static str {
+            self.0
+        }
+
+        fn directives(&self) -> &[&'static str] {
+            &["TAG:"]
+        }
+
+        fn directive(&self, _keyword: &str, argument: &str) -> Result<ArcH, String> {
+            Ok(ArcH::Single {
+                vertex: Vertex::new(vec![self.0.to_string(), argument.to_string()]),
+                is_single_child: false,
+            })
+        }
+
+        fn fish_labels(&self) -> &[&'static str] {
+            &["tag:*"]
+        }
+
+        fn fish(&self, mut fish: Fish) -> Result<Fish, String> {
+            if fish.label.ends_with(":bad") {
+                return Err(format!("`{}` is bad", fish.label));
+            }
+            fish.label.push_str(&format!(":{}", self.0));
+            Ok(fish)
+        }
+
+        fn transform_arches(
+            &self,
+            section: &str,
+            mut arches: Vec<ArcH>,
+        ) -> Result<Vec<ArcH>, String> {
+            if section == "Bad" {
+                return Err("cannot transform `Bad`".to_string());
+            }
+            arches.push(ArcH::Single {
+                vertex: Vertex::new(vec![self.0.to_string()]),
+                is_single_child: false,
+            });
+            Ok(arches)
+        }
+    }
+
+    fn registry(names: &[&'static str]) -> Registry {
+        let mut registry = Registry::new();
+        for name in names {
+            registry.register(Box::new(Tag(name)));
+        }
+        registry
+    }
+
+    fn head(arch: &ArcH) -> String {
+        match arch {
+            ArcH::Single { vertex, .. } => vertex.name(),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn plugins_run_in_registration_order() {
+        let registry = registry(&["first", "second"]);
+        assert_eq!(registry.names(), ["first", "second"]);
+
+        let arches = registry.transform_arches("UI::App", vec![]).unwrap();
+        let heads: Vec<String> = arches.iter().map(head).collect();
+        assert_eq!(heads, ["first", "second"]);
+
+        let fish = registry.fish(Fish::new("tag:x")).unwrap();
+        assert_eq!(fish.label, "tag:x:first:second");
+        // labels no plugin claims are left alone
+        assert_eq!(
+            registry.fish(Fish::new("renders")).unwrap().label,
+            "renders"
+        );
+
+        // the first plugin claiming a directive gets it
+        let (plugin, keyword, argument) = registry.directive_for("  TAG:  x ").unwrap();
+        assert_eq!((plugin.name(), keyword, argument), ("first", "TAG:", "x"));
+        assert_eq!(
+            head(&plugin.directive(keyword, argument).unwrap()),
+            "first::x"
+        );
+        assert!(registry.directive_for("UI::App").is_none());
+    }
+
+    #[test]
+    fn plugin_errors_are_diagnostics() {
+        let registry = registry(&["first", "second"]);
+        let mut fish = Fish::new("tag:bad");
+        fish.span = Some(Span { start: 3, end: 10 });
+        let error = registry.fish(fish).unwrap_err();
+        assert_eq!(
+            error,
+            PluginError {
+                plugin: "first",
+                message: "`tag:bad` is bad".to_string(),
+                span: Some(Span { start: 3, end: 10 }),
+            }
+        );
+        let diagnostic = crate::ParseError::Plugin(error).to_diagnostic();
+        assert_eq!(diagnostic.code, "plugin");
+        assert_eq!(diagnostic.message, "`tag:bad` is bad (plugin first)");
+        assert_eq!(diagnostic.span, Some(Span { start: 3, end: 10 }));
+
+        let error = registry.transform_arches("Bad", vec![]).unwrap_err();
+        assert_eq!((error.plugin, error.span), ("first", None));
+        assert_eq!(error.message, "cannot transform `Bad`");
+    }
+}
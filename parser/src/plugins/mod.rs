@@ -0,0 +1,14 @@
+use crate::plugin::Plugin;
+
+#[cfg(feature = "route-plugin")]
+mod route;
+
+// Every plugin compiled into the parser, in the order they are consulted.
+// To add one, put it in a module next to this file and list it here (behind a feature
+// if not everybody should get it).
+pub fn registered() -> Vec<Box<dyn Plugin>> {
+    vec![
+        #[cfg(feature = "route-plugin")]
+        Box::new(route::Route),
+    ]
+}
@@ -0,0 +1,84 @@
+use crate::arch::{ArcH, Fish, Vertex};
+use crate::plugin::Plugin;
+
+const METHODS: [&str; 5] = ["get", "post", "put", "patch", "delete"];
+
+// Example plugin, enabled with `--features route-plugin`:
+//
+//   ROUTE: GET /tasks -> Logic::listTasks
+//
+// is read as `HTTP::GET /tasks ><http:get> Logic::listTasks`, and fish labelled
+// `http:<method>` are checked for a known method.
+pub struct Route;
+
+impl Plugin for Route {
+    fn name(&self) -> &'static str {
+        "route"
+    }
+
+    fn directives(&self) -> &[&'static str] {
+        &["ROUTE:"]
+    }
+
+    fn directive(&self, _keyword: &str, argument: &str) -> Result<ArcH, String> {
+        let (route, handler) = argument
+            .split_once("->")
+            .ok_or("expected `ROUTE: METHOD /path -> Handler`")?;
+        let route = route.trim();
+        let method = route.split_whitespace().next().unwrap_or("");
+        Ok(ArcH::ArcH {
+            is_single_child: false,
+            vertex: Vertex::new(vec!["HTTP".to_string(), route.to_string()]),
+            fish: self.fish(Fish::new(&format!("http:{}", method.to_lowercase())))?,
+            next: Box::new(ArcH::Single {
+                is_single_child: false,
+                vertex: Vertex::new(handler.split("::").map(|s| s.trim().to_string()).collect()),
+            }),
+        })
+    }
+
+    fn fish_labels(&self) -> &[&'static str] {
+        &["http:*"]
+    }
+
+    fn fish(&self, fish: Fish) -> Result<Fish, String> {
+        let method = &fish.label["http:".len()..];
+        if METHODS.contains(&method) {
+            Ok(fish)
+        } else {
+            Err(format!("unknown HTTP method `{}`", method))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{error, written};
+
+    #[test]
+    fn routes_are_edges() {
+        assert_eq!(
+            written("[API]\nROUTE: GET /tasks -> Logic::listTasks\n"),
+            ["API ><> HTTP::GET /tasks ><http:get> Logic::listTasks"]
+        );
+    }
+
+    #[test]
+    fn unknown_methods() {
+        assert_eq!(
+            error("[API]\nROUTE: FETCH /tasks -> Logic::listTasks\n"),
+            (
+                "unknown HTTP method `fetch` (plugin route)".to_string(),
+                Some((2, 1))
+            )
+        );
+        assert_eq!(
+            error("[API]\nUI::List ><http:fetch> API::Tasks\n").0,
+            "unknown HTTP method `fetch` (plugin route)"
+        );
+        assert_eq!(
+            error("[API]\nROUTE: GET /tasks\n").0,
+            "expected `ROUTE: METHOD /path -> Handler` (plugin route)"
+        );
+    }
+}
@@ -0,0 +1,452 @@
+use crate::arch::{OriginalArcHForm, Vertex};
+use crate::attributes::Attributes;
+use crate::diagnostics;
+use crate::graph::{FishFilter, Graph};
+use crate::output;
+use crate::pattern::VertexPattern;
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+
+const USAGE: &str =
+    "usage: rust-parser query [--format table|json] [--define NAME=VALUE]... [--flag NAME]... <file> <query>
+
+queries (PATTERN is a vertex, with * for one segment and ** for any number):
+  vertices PATTERN                            the vertices matching PATTERN
+  out PATTERN [via FISH]                      edges leaving PATTERN
+  in PATTERN [via FISH]                       edges arriving at PATTERN
+  reach PATTERN [via FISH]                    what PATTERN reaches, with the distance
+  reaching PATTERN [via FISH]                 what reaches PATTERN, with the distance
+  paths PATTERN to PATTERN [via FISH] [limit N]  the paths between them
+
+FISH is a fish label, with * wildcards; without `via` every fish is followed.";
+
+// paths stop after this many results unless `limit N` is given
+const DEFAULT_PATH_LIMIT: usize = 100;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Query {
+    Vertices(VertexPattern),
+    Out(VertexPattern, Option<String>),
+    In(VertexPattern, Option<String>),
+    Reach(VertexPattern, Option<String>),
+    Reaching(VertexPattern, Option<String>),
+    Paths {
+        from: VertexPattern,
+        to: VertexPattern,
+        fish: Option<String>,
+        limit: usize,
+    },
+}
+
+fn pattern(word: Option<&&str>, keyword: &str) -> Result<VertexPattern, String> {
+    let word = word.ok_or(format!("`{}` expects a vertex pattern", keyword))?;
+    let pattern = VertexPattern::parse(word);
+    if pattern.0.iter().any(|s| s.is_empty()) {
+        return Err(format!("`{}` is not a vertex pattern", word));
+    }
+    Ok(pattern)
+}
+
+impl Query {
+    // `reaching DB::Users via writes`
+    pub fn parse(text: &str) -> Result<Query, String> {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        let keyword = *words.first().ok_or("empty query")?;
+        let mut fish = None;
+        let mut limit = DEFAULT_PATH_LIMIT;
+        let mut to = None;
+
+        // everything after the subject is `word value` options
+        let mut rest = words.iter().skip(2);
+        while let Some(word) = rest.next() {
+            let value = rest.next().ok_or(format!("`{}` expects a value", word))?;
+            match *word {
+                "via" => fish = Some(value.to_string()),
+                "to" if keyword == "paths" => to = Some(pattern(Some(value), "to")?),
+                "limit" if keyword == "paths" => {
+                    limit = value
+                        .parse()
+                        .map_err(|_| format!("`{}` is not a number", value))?
+                }
+                _ => return Err(format!("unexpected `{}`", word)),
+            }
+        }
+
+        let subject = pattern(words.get(1), keyword)?;
+        Ok(match keyword {
+            "vertices" if fish.is_some() => return Err("`vertices` does not take `via`".into()),
+            "vertices" => Query::Vertices(subject),
+            "out" => Query::Out(subject, fish),
+            "in" => Query::In(subject, fish),
+            "reach" => Query::Reach(subject, fish),
+            "reaching" => Query::Reaching(subject, fish),
+            "paths" => Query::Paths {
+                from: subject,
+                to: to.ok_or("`paths` expects `to PATTERN`")?,
+                fish,
+                limit,
+            },
+            _ => return Err(format!("unknown query `{}`", keyword)),
+        })
+    }
+}
+
+// The result of a query: a table with named columns
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rows {
+    pub columns: Vec<&'static str>,
+    pub rows: Vec<Vec<Value>>,
+}
+
+fn name(v: &Vertex) -> Value {
+    Value::String(v.name())
+}
+
+impl Rows {
+    // an `attributes` column, one value per row, if any row has attributes
+    fn with_attributes(mut self, attributes: Vec<Option<&Attributes>>) -> Rows {
+        if attributes.iter().all(Option::is_none) {
+            return self;
+        }
+        self.columns.push("attributes");
+        for (row, a) in self.rows.iter_mut().zip(attributes) {
+            row.push(serde_json::to_value(a.cloned().unwrap_or_default()).unwrap_or_default());
+        }
+        self
+    }
+}
+
+// vertex -> number of steps it takes to get there
+fn distances(
+    graph: &Graph,
+    start: &BTreeSet<Vertex>,
+    fish: FishFilter,
+    reverse: bool,
+) -> BTreeMap<Vertex, usize> {
+    let mut seen = BTreeMap::new();
+    let mut frontier = start.clone();
+    let mut depth = 0;
+    while !frontier.is_empty() {
+        depth += 1;
+        let next = if reverse {
+            graph.predecessors(&frontier, fish)
+        } else {
+            graph.successors(&frontier, fish)
+        };
+        frontier = next.into_iter().filter(|v| !seen.contains_key(v)).collect();
+        for v in &frontier {
+            seen.insert(v.clone(), depth);
+        }
+    }
+    seen
+}
+
+pub fn execute(graph: &Graph, query: &Query) -> Rows {
+    match query {
+        Query::Vertices(pattern) => {
+            let vertices = graph.matching(pattern);
+            Rows {
+                columns: vec!["vertex"],
+                rows: vertices.iter().map(|v| vec![name(v)]).collect(),
+            }
+            .with_attributes(
+                vertices
+                    .iter()
+                    .map(|v| graph.vertex_attributes(v))
+                    .collect(),
+            )
+        }
+        Query::Out(pattern, fish) | Query::In(pattern, fish) => {
+            let outgoing = matches!(query, Query::Out(..));
+            let fish = fish.as_deref();
+            let mut rows = vec![];
+            let mut attributes = vec![];
+            for v in graph.matching(pattern) {
+                let edges: Vec<_> = if outgoing {
+                    graph.edges_from(&v).collect()
+                } else {
+                    graph.edges_to(&v).collect()
+                };
+                for (label, other) in edges {
+                    if fish.is_some_and(|f| !crate::pattern::glob(f, label)) {
+                        continue;
+                    }
+                    let (from, to) = if outgoing { (&v, other) } else { (other, &v) };
+                    rows.push(vec![name(from), Value::String(label.clone()), name(to)]);
+                    attributes.push(graph.edge_attributes(from, label, to));
+                }
+            }
+            Rows {
+                columns: vec!["from", "fish", "to"],
+                rows,
+            }
+            .with_attributes(attributes)
+        }
+        Query::Reach(pattern, fish) | Query::Reaching(pattern, fish) => {
+            let reverse = matches!(query, Query::Reaching(..));
+            let found = distances(graph, &graph.matching(pattern), fish.as_deref(), reverse);
+            let mut found: Vec<_> = found.into_iter().collect();
+            found.sort_by(|(a, da), (b, db)| da.cmp(db).then(a.cmp(b)));
+            Rows {
+                columns: vec!["vertex", "distance"],
+                rows: found
+                    .iter()
+                    .map(|(v, d)| vec![name(v), Value::from(*d)])
+                    .collect(),
+            }
+        }
+        Query::Paths {
+            from,
+            to,
+            fish,
+            limit,
+        } => {
+            let paths = graph.paths(
+                &graph.matching(from),
+                &graph.matching(to),
+                fish.as_deref(),
+                *limit,
+            );
+            Rows {
+                columns: vec!["length", "path"],
+                rows: paths
+                    .iter()
+                    .map(|p| vec![Value::from(p.steps.len()), Value::String(p.to_fish())])
+                    .collect(),
+            }
+        }
+    }
+}
+
+fn cell(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Object(o) if o.is_empty() => String::new(),
+        other => other.to_string(),
+    }
+}
+
+pub fn render_table(rows: &Rows) -> String {
+    let cells: Vec<Vec<String>> = rows
+        .rows
+        .iter()
+        .map(|row| row.iter().map(cell).collect())
+        .collect();
+    let widths: Vec<usize> = rows
+        .columns
+        .iter()
+        .enumerate()
+        .map(|(i, c)| cells.iter().map(|r| r[i].len()).fold(c.len(), usize::max))
+        .collect();
+    let line = |row: Vec<String>| {
+        let padded: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(c, w)| format!("{:w$}", c, w = w))
+            .collect();
+        padded.join("  ").trim_end().to_string() + "\n"
+    };
+    let mut out = line(rows.columns.iter().map(|c| c.to_string()).collect());
+    out += &line(widths.iter().map(|w| "-".repeat(*w)).collect());
+    for row in cells {
+        out += &line(row);
+    }
+    out
+}
+
+pub fn render_json(rows: &Rows) -> Value {
+    Value::Array(
+        rows.rows
+            .iter()
+            .map(|row| {
+                let object: Map<String, Value> = rows
+                    .columns
+                    .iter()
+                    .map(|c| c.to_string())
+                    .zip(row.iter().cloned())
+                    .collect();
+                Value::Object(object)
+            })
+            .collect(),
+    )
+}
+
+// a .fish file, parsed with `options`, or a model in JSON (e.g. an output.json)
+pub fn load_forms(
+    path: &str,
+    options: &crate::ParseOptions,
+) -> Result<Vec<OriginalArcHForm>, Box<dyn Error>> {
+    if path.ends_with(".json") {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        let document =
+            output::Document::from_json(&text).map_err(|e| format!("{}: {}", path, e))?;
+        return Ok(document.forms());
+    }
+    let source = crate::read_source(path).map_err(|e| format!("{}: {}", path, e))?;
+    let sections = match crate::parse_inputs_with(&source, options) {
+        Ok(sections) => sections,
+        Err(e) => {
+            diagnostics::report(&[e.to_diagnostic()], path, &source);
+            std::process::exit(1);
+        }
+    };
+    Ok(crate::collect_inputs(&source, sections)
+        .into_iter()
+        .flat_map(|(_, forms)| forms)
+        .collect())
+}
+
+// `rust-parser query ...`
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut as_json = false;
+    let mut options = crate::ParseOptions::default();
+    let mut path = None;
+    let mut words = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        // options go before the query, whose words are taken as they are
+        if words.is_empty() && options.take(arg, &mut args)? {
+            continue;
+        }
+        match arg.as_str() {
+            "--format" => match args.next().map(|f| f.as_str()) {
+                Some("table") => as_json = false,
+                Some("json") => as_json = true,
+                _ => crate::usage(USAGE),
+            },
+            _ if path.is_none() => path = Some(arg.clone()),
+            _ => words.push(arg.as_str()),
+        }
+    }
+    let path = path.unwrap_or_else(|| crate::usage(USAGE));
+    if words.is_empty() {
+        crate::usage(USAGE);
+    }
+    let query = Query::parse(&words.join(" "))?;
+
+    let graph = Graph::new(&load_forms(&path, &options)?);
+    let rows = execute(&graph, &query);
+    if as_json {
+        println!("{}", serde_json::to_string_pretty(&render_json(&rows))?);
+    } else {
+        print!("{}", render_table(&rows));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{forms, MODEL};
+
+    // the rows of `query` on MODEL, one string per row
+    fn rows(query: &str) -> Vec<String> {
+        execute(&Graph::new(&forms(MODEL)), &Query::parse(query).unwrap())
+            .rows
+            .iter()
+            .map(|row| row.iter().map(cell).collect::<Vec<_>>().join(" | "))
+            .collect()
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            Query::parse("out UI::* via re*"),
+            Ok(Query::Out(
+                VertexPattern::parse("UI::*"),
+                Some("re*".to_string())
+            ))
+        );
+        assert_eq!(
+            Query::parse("paths UI::App to DB::** limit 5"),
+            Ok(Query::Paths {
+                from: VertexPattern::parse("UI::App"),
+                to: VertexPattern::parse("DB::**"),
+                fish: None,
+                limit: 5,
+            })
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        let error = |text: &str| Query::parse(text).unwrap_err();
+        assert_eq!(error(""), "empty query");
+        assert_eq!(error("around UI::App"), "unknown query `around`");
+        assert_eq!(error("out"), "`out` expects a vertex pattern");
+        assert_eq!(error("out UI::"), "`UI::` is not a vertex pattern");
+        assert_eq!(error("out UI::App via"), "`via` expects a value");
+        assert_eq!(error("out UI::App to DB::Tasks"), "unexpected `to`");
+        assert_eq!(
+            error("vertices UI::* via x"),
+            "`vertices` does not take `via`"
+        );
+        assert_eq!(error("paths UI::App"), "`paths` expects `to PATTERN`");
+        assert_eq!(
+            error("paths UI::App to DB::Tasks limit ten"),
+            "`ten` is not a number"
+        );
+    }
+
+    #[test]
+    fn queries() {
+        assert_eq!(
+            rows("vertices UI::*"),
+            [
+                "UI::App | ",
+                "UI::Form | {\"owner\":\"forms\"}",
+                "UI::List | "
+            ]
+        );
+        assert_eq!(
+            rows("out Logic::* via w*"),
+            ["Logic::save | writes | DB::Tasks | {\"async\":true}"]
+        );
+        assert_eq!(
+            rows("in DB::Tasks"),
+            [
+                "Logic::load | reads | DB::Tasks | ",
+                "Logic::save | writes | DB::Tasks | {\"async\":true}",
+            ]
+        );
+        assert_eq!(rows("reaching DB::Tasks via w*"), ["Logic::save | 1"]);
+        assert_eq!(
+            rows("reach UI::App"),
+            [
+                "UI::Form | 1",
+                "UI::List | 1",
+                "Logic::load | 2",
+                "Logic::save | 2",
+                "DB::Tasks | 3",
+            ]
+        );
+        assert_eq!(rows("paths UI::App to DB::Tasks limit 1").len(), 1);
+        assert_eq!(
+            rows("paths UI::** to DB::Tasks via *a*"),
+            ["2 | UI::List ><calls> Logic::load ><reads> DB::Tasks"]
+        );
+    }
+
+    #[test]
+    fn rendering() {
+        let rows = Rows {
+            columns: vec!["vertex", "distance"],
+            rows: vec![
+                vec![Value::from("UI::List"), Value::from(1)],
+                vec![Value::from("DB::Tasks"), Value::from(12)],
+            ],
+        };
+        assert_eq!(
+            render_table(&rows),
+            "vertex     distance\n---------  --------\nUI::List   1\nDB::Tasks  12\n"
+        );
+        assert_eq!(
+            render_json(&rows),
+            serde_json::json!([
+                { "vertex": "UI::List", "distance": 1 },
+                { "vertex": "DB::Tasks", "distance": 12 },
+            ])
+        );
+    }
+}
@@ -0,0 +1,294 @@
+use crate::arch::{OriginalArcHForm, Span, Vertex};
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::pattern::VertexPattern;
+use crate::validate::{Rule, RuleConfig};
+use std::collections::BTreeMap;
+
+// name of the section that holds the schema; it is not part of the graph
+pub const SECTION: &str = "schema";
+
+// Schema declarations, e.g.
+//
+//   [schema]
+//   ## kinds: a name and the vertex patterns it covers
+//   kind UI = UI::*, UI::**::Button
+//   kind DB = DB::*
+//   ## relations: which kinds a fish may connect (`*` is any vertex)
+//   UI ><renders> UI
+//   UI ><writes> DB
+//
+// A fish can have several relations; an edge is fine if it fits any of them.
+// Like any section, the schema runs until the next [section] header.
+#[derive(Debug, Clone, Default)]
+pub struct Schema {
+    pub kinds: BTreeMap<String, Vec<VertexPattern>>,
+    pub relations: Vec<Relation>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Relation {
+    pub from: String,
+    pub fish: String,
+    pub to: String,
+    pub span: Option<Span>,
+}
+
+impl Relation {
+    fn signature(&self) -> String {
+        format!("{} ><{}> {}", self.from, self.fish, self.to)
+    }
+}
+
+impl Schema {
+    pub fn is_kind(&self, vertex: &Vertex, kind: &str) -> bool {
+        if kind == "*" {
+            return true;
+        }
+        match self.kinds.get(kind) {
+            Some(patterns) => patterns.iter().any(|p| p.matches(vertex)),
+            None => false,
+        }
+    }
+
+    pub fn relations_of(&self, fish: &str) -> Vec<&Relation> {
+        self.relations.iter().filter(|r| r.fish == fish).collect()
+    }
+}
+
+fn schema_error(message: String, span: Option<Span>) -> Diagnostic {
+    Diagnostic::new(Severity::Error, "schema", message, span)
+}
+
+// Parse the [schema] sections among the sections of a source text, as split by the
+// parser, so comments, fences and #if blocks have already been dealt with.
+// Returns None if there is no schema, plus any errors in the declarations.
+pub fn parse_schema(
+    source: &str,
+    sections: &[(&str, Vec<&str>)],
+) -> (Option<Schema>, Vec<Diagnostic>) {
+    let mut errors = Vec::new();
+    let mut lines = sections
+        .iter()
+        .filter(|(name, _)| *name == SECTION)
+        .peekable();
+    // an empty [schema] still declares that every fish needs a relation
+    let mut schema = lines.peek().map(|_| Schema::default());
+    for line in lines.flat_map(|(_, lines)| lines) {
+        let schema = schema.as_mut().unwrap();
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let span = Span::within(source, line);
+        if let Some(rest) = line.strip_prefix("kind ") {
+            match rest.split_once('=') {
+                Some((name, patterns)) if !name.trim().is_empty() => {
+                    let patterns = patterns.split(',').map(VertexPattern::parse).collect();
+                    if schema
+                        .kinds
+                        .insert(name.trim().to_string(), patterns)
+                        .is_some()
+                    {
+                        errors.push(schema_error(
+                            format!("kind `{}` is declared twice", name.trim()),
+                            span,
+                        ));
+                    }
+                }
+                _ => errors.push(schema_error(
+                    "expected `kind NAME = PATTERN, ...`".to_string(),
+                    span,
+                )),
+            }
+        } else {
+            match parse_relation(line) {
+                Some((from, fish, to)) => schema.relations.push(Relation {
+                    from: from.to_string(),
+                    fish: fish.to_string(),
+                    to: to.to_string(),
+                    span,
+                }),
+                None => errors.push(schema_error(
+                    "expected `kind NAME = PATTERN, ...` or `KIND ><fish> KIND`".to_string(),
+                    span,
+                )),
+            }
+        }
+    }
+
+    if let Some(schema) = &schema {
+        for relation in &schema.relations {
+            for kind in [&relation.from, &relation.to] {
+                if kind != "*" && !schema.kinds.contains_key(kind.as_str()) {
+                    errors.push(schema_error(
+                        format!("unknown kind `{}`", kind),
+                        relation.span,
+                    ));
+                }
+            }
+        }
+    }
+    (schema, errors)
+}
+
+// `FROM ><fish> TO`
+fn parse_relation(line: &str) -> Option<(&str, &str, &str)> {
+    let (from, rest) = line.split_once("><")?;
+    let (fish, to) = rest.split_once('>')?;
+    let (from, fish, to) = (from.trim(), fish.trim(), to.trim());
+    if from.is_empty() || fish.is_empty() || to.is_empty() {
+        return None;
+    }
+    Some((from, fish, to))
+}
+
+// Check every edge of the flattened model against the relation signatures
+pub fn check(schema: &Schema, forms: &[OriginalArcHForm], config: &RuleConfig) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    for form in forms {
+        for (from, fish, to) in form.edges() {
+            if fish.label.is_empty() || from.is_placeholder() || to.is_placeholder() {
+                continue;
+            }
+            let relations = schema.relations_of(&fish.label);
+            let (rule, message) = if relations.is_empty() {
+                (
+                    Rule::UndeclaredFish,
+                    format!("`{}` is not declared in the schema", fish.label),
+                )
+            } else if !relations
+                .iter()
+                .any(|r| schema.is_kind(from, &r.from) && schema.is_kind(to, &r.to))
+            {
+                let allowed: Vec<String> = relations.iter().map(|r| r.signature()).collect();
+                (
+                    Rule::SchemaViolation,
+                    format!(
+                        "`{} ><{}> {}` does not fit the schema (allowed: {})",
+                        from.name(),
+                        fish.label,
+                        to.name(),
+                        allowed.join(", ")
+                    ),
+                )
+            } else {
+                continue;
+            };
+            if let Some(severity) = config.severity(rule) {
+                diagnostics.push(Diagnostic::new(
+                    severity,
+                    rule.code(),
+                    message,
+                    fish.span.or(to.span),
+                ));
+            }
+        }
+    }
+    diagnostics.sort_by(|a, b| (a.span, &a.message).cmp(&(b.span, &b.message)));
+    diagnostics.dedup();
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the schema of `source`, and the messages and lines of the errors in it
+    fn declarations(source: &str) -> (Option<Schema>, Vec<(String, usize)>) {
+        let sections = crate::split_sections(source, &crate::DEFAULT_OPTIONS).unwrap();
+        let (schema, errors) = parse_schema(source, &sections);
+        let errors = errors
+            .into_iter()
+            .map(|d| (d.message, d.span.unwrap().line_col(source).0))
+            .collect();
+        (schema, errors)
+    }
+
+    // the codes and messages the schema rules find in `source`
+    fn check_source(source: &str, config: &RuleConfig) -> Vec<(String, String)> {
+        let sections = crate::split_sections(source, &crate::DEFAULT_OPTIONS).unwrap();
+        let schema = parse_schema(source, &sections).0.unwrap();
+        check(&schema, &crate::testing::forms(source), config)
+            .into_iter()
+            .map(|d| (d.code, d.message))
+            .collect()
+    }
+
+    #[test]
+    fn kinds_and_relations() {
+        let source = "[UI::App]\nUI::List ><reads> DB::Tasks\n\n\
+                      [schema]\nkind UI = UI::*, UI::**::Button\nkind DB = DB::* ## tables\n\
+                      UI ><renders> UI\nUI ><reads> DB\n* ><logs> *\n";
+        let (schema, errors) = declarations(source);
+        let schema = schema.unwrap();
+        assert_eq!(errors, []);
+        assert_eq!(schema.kinds.keys().collect::<Vec<_>>(), ["DB", "UI"]);
+        assert_eq!(schema.kinds["UI"].len(), 2);
+        let relations: Vec<String> = schema.relations.iter().map(|r| r.signature()).collect();
+        assert_eq!(
+            relations,
+            ["UI ><renders> UI", "UI ><reads> DB", "* ><logs> *"]
+        );
+        assert_eq!(schema.relations[1].span.unwrap().line_col(source), (8, 1));
+
+        assert!(declarations("[UI::App]\nA ><x> B\n").0.is_none());
+        // an empty schema still is one
+        assert!(declarations("[schema]\n[UI::App]\nA ><x> B\n").0.is_some());
+    }
+
+    #[test]
+    fn comments_fences_and_conditions_are_not_declarations() {
+        let source = "[schema]\nkind UI = UI::*\n##{\nkind DB = DB::*\n##}\n\
+                      #if tables\nkind Table = DB::*\n#endif\n\
+                      ## UI ><reads> DB\nUI ><renders> UI\n\n\
+                      [UI::App]\nUI::List ><shows> ```\n[schema]\n```\n";
+        let (schema, errors) = declarations(source);
+        assert_eq!(errors, []);
+        let schema = schema.unwrap();
+        assert_eq!(schema.kinds.keys().collect::<Vec<_>>(), ["UI"]);
+        assert_eq!(schema.relations.len(), 1);
+
+        // a header that is commented out does not start a schema
+        let (schema, _) = declarations("[UI::App]\nA ><x> B\n##{\n[schema]\n##}\n");
+        assert!(schema.is_none());
+    }
+
+    #[test]
+    fn errors_in_declarations() {
+        let source = "[schema]\nkind UI = UI::*\nkind UI = App::*\nkind = DB::*\n\
+                      UI renders DB\nUI ><reads> DB\n";
+        assert_eq!(
+            declarations(source).1,
+            [
+                ("kind `UI` is declared twice".to_string(), 3),
+                ("expected `kind NAME = PATTERN, ...`".to_string(), 4),
+                (
+                    "expected `kind NAME = PATTERN, ...` or `KIND ><fish> KIND`".to_string(),
+                    5
+                ),
+                ("unknown kind `DB`".to_string(), 6),
+            ]
+        );
+    }
+
+    #[test]
+    fn edges_that_do_not_fit() {
+        let source = "[UI::App]\nUI::List ><reads> DB::Tasks\nUI::List ><writes> DB::Tasks\n\
+                      DB::Tasks ><reads> UI::List\n\n\
+                      [schema]\nkind UI = UI::*\nkind DB = DB::*\nUI ><reads> DB\n";
+        let mut config = RuleConfig::default();
+        assert_eq!(
+            check_source(source, &config),
+            [(
+                "schema-violation".to_string(),
+                "`DB::Tasks ><reads> UI::List` does not fit the schema (allowed: UI ><reads> DB)"
+                    .to_string()
+            )]
+        );
+        config.set(Rule::UndeclaredFish, Some(Severity::Warning));
+        assert!(check_source(source, &config).contains(&(
+            "undeclared-fish".to_string(),
+            "`writes` is not declared in the schema".to_string()
+        )));
+    }
+}
@@ -0,0 +1,95 @@
+// Sets fan a chain out inline: `{B, C::D, E}` stands for several vertices at once, so
+//
+//   UI::App ><renders> {UI::List, UI::Form} ><calls> Logic::save
+//
+// is the same as writing each path on its own. A set may appear anywhere a vertex can,
+// and may contain other sets, which are flattened.
+
+// Where the `{` at the start of `s` is closed: the byte after its `}`. Braces nest, and
+// quotes and $( ... ) are skipped.
+pub fn end(s: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut i = 0;
+    while i < s.len() {
+        let rest = &s[i..];
+        let c = rest.chars().next()?;
+        i += match c {
+            '{' => {
+                depth += 1;
+                1
+            }
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i + 1);
+                }
+                1
+            }
+            '"' => rest[1..].find('"').map_or(rest.len(), |end| end + 2),
+            '$' if rest.starts_with("$(") => crate::expression_end(rest)?,
+            _ => c.len_utf8(),
+        };
+    }
+    None
+}
+
+// The elements of a set, e.g. "{B, {C, D}}" gives "B" and "{C, D}"
+pub fn elements(set: &str) -> Vec<&str> {
+    let inner = &set[1..set.len() - 1];
+    let mut elements = Vec::new();
+    let mut start = 0;
+    let mut i = 0;
+    while i < inner.len() {
+        let rest = &inner[i..];
+        let c = rest.chars().next().unwrap_or(',');
+        let skip = match c {
+            '{' => end(rest).unwrap_or(rest.len()),
+            '"' => rest[1..].find('"').map_or(rest.len(), |end| end + 2),
+            '$' if rest.starts_with("$(") => crate::expression_end(rest).unwrap_or(rest.len()),
+            ',' => {
+                elements.push(inner[start..i].trim());
+                start = i + 1;
+                1
+            }
+            _ => c.len_utf8(),
+        };
+        i += skip;
+    }
+    elements.push(inner[start..].trim());
+    elements.retain(|e| !e.is_empty());
+    elements
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(s: &str) -> Option<&str> {
+        end(s).map(|i| &s[..i])
+    }
+
+    #[test]
+    fn sets_end_at_their_matching_brace() {
+        assert_eq!(set("{B, C} ><x> D"), Some("{B, C}"));
+        assert_eq!(set("{B, {C, D}}{x=1} ><x> E"), Some("{B, {C, D}}"));
+        // braces in quotes and expressions do not count
+        assert_eq!(set(r#"{"}", B} x"#), Some(r#"{"}", B}"#));
+        assert_eq!(set("{$(f(\"}\")), B} x"), Some("{$(f(\"}\")), B}"));
+        assert_eq!(set("{B, {C}"), None);
+        assert_eq!(set("{B, $(C}"), None);
+    }
+
+    #[test]
+    fn elements_are_split_at_top_level_commas() {
+        assert_eq!(elements("{B, C::D, E}"), ["B", "C::D", "E"]);
+        assert_eq!(elements("{B, {C, D}}"), ["B", "{C, D}"]);
+        // commas of attributes, literals and expressions stay where they are
+        assert_eq!(
+            elements(r#"{B{x=1, y=2}, "a, b", $(f(1, 2))}"#),
+            ["B{x=1, y=2}", r#""a, b""#, "$(f(1, 2))"]
+        );
+        // empty elements are dropped
+        assert_eq!(elements("{B,, C, }"), ["B", "C"]);
+        assert!(elements("{ }").is_empty());
+    }
+}
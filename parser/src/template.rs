@@ -0,0 +1,330 @@
+use crate::arch::{markAsSingleChild, ArcH, Span, Vertex};
+use crate::attributes;
+use std::collections::BTreeMap;
+
+// Templates paste a parameterised block of statements wherever they are used:
+//
+//   [template CrudScreen(x)]
+//   UI::${x}List
+//   UI::${x}Form ><submits> Logic::save${x}
+//
+//   [UI::App]
+//   UI::TaskScreen ><renders> @CrudScreen(Task)
+//
+// `@Name(arg, ...)` may stand for a whole statement, a `- ` child, or the end of a chain,
+// where the template's statements fan out like `- ` children. `${param}` is replaced in
+// vertices, fish labels and attribute values. Expansion happens on the parsed ArcH trees, so the pasted
+// vertices keep their spans in the template and get the call's span as `call_site`.
+
+pub const KEYWORD: &str = "template";
+
+#[derive(Debug, Clone)]
+pub struct Template {
+    pub params: Vec<String>,
+    pub body: Vec<ArcH>,
+}
+
+pub type Templates = BTreeMap<String, Template>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TemplateError {
+    pub message: String,
+    pub span: Option<Span>,
+}
+
+// "Name(a, b)" -> ("Name", ["a", "b"])
+fn parse_call(text: &str) -> Option<(&str, Vec<String>)> {
+    let (name, rest) = text.split_once('(')?;
+    let args = rest.trim_end().strip_suffix(')')?;
+    let name = name.trim();
+    if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        return None;
+    }
+    let args = if args.trim().is_empty() {
+        vec![]
+    } else {
+        args.split(',').map(|a| a.trim().to_string()).collect()
+    };
+    Some((name, args))
+}
+
+// The section name of a template definition, e.g. "template CrudScreen(x)"
+pub fn parse_header(section: &str) -> Option<Result<(String, Vec<String>), String>> {
+    let rest = section.strip_prefix(KEYWORD)?.strip_prefix(' ')?;
+    let invalid = || {
+        format!(
+            "expected `[template Name(param, ...)]`, found `[{}]`",
+            section
+        )
+    };
+    Some(match parse_call(rest.trim()) {
+        Some((name, params)) if params.iter().all(|p| !p.is_empty()) => {
+            Ok((name.to_string(), params))
+        }
+        _ => Err(invalid()),
+    })
+}
+
+// `@Name(arg, ...)` used as a vertex
+fn call_of(vertex: &Vertex) -> Option<(String, Vec<String>)> {
+    let text = vertex.name();
+    let (name, args) = parse_call(text.strip_prefix('@')?)?;
+    Some((name.to_string(), args))
+}
+
+struct Expander<'a> {
+    templates: &'a Templates,
+    // templates being expanded, to catch recursion
+    stack: Vec<String>,
+}
+
+pub fn expand(arches: Vec<ArcH>, templates: &Templates) -> Result<Vec<ArcH>, TemplateError> {
+    let mut expander = Expander {
+        templates,
+        stack: vec![],
+    };
+    expander.statements(arches)
+}
+
+impl Expander<'_> {
+    fn statements(&mut self, arches: Vec<ArcH>) -> Result<Vec<ArcH>, TemplateError> {
+        let mut expanded = Vec::new();
+        for arch in arches {
+            expanded.extend(self.statement(arch)?);
+        }
+        Ok(expanded)
+    }
+
+    // a statement or a child: a call becomes the template's statements
+    fn statement(&mut self, arch: ArcH) -> Result<Vec<ArcH>, TemplateError> {
+        match arch {
+            ArcH::Single {
+                vertex,
+                is_single_child,
+            } if call_of(&vertex).is_some() => {
+                let body = self.instantiate(&vertex)?;
+                if is_single_child {
+                    Ok(body.into_iter().map(markAsSingleChild).collect())
+                } else {
+                    Ok(body)
+                }
+            }
+            other => Ok(vec![self.node(other)?]),
+        }
+    }
+
+    // inside a statement, a call may only end a chain
+    fn node(&mut self, arch: ArcH) -> Result<ArcH, TemplateError> {
+        match arch {
+            ArcH::ArcH {
+                vertex,
+                fish,
+                next,
+                is_single_child,
+            } => {
+                reject_call(&vertex)?;
+                let next = match *next {
+                    ArcH::Single { vertex: end, .. } if call_of(&end).is_some() => {
+                        // fan out from an empty vertex, as for `><fish>` followed by `- ` children
+                        let mut placeholder = Vertex::placeholder();
+                        placeholder.span = end.span;
+                        ArcH::ArcHWithNewLines {
+                            prefix: Box::new(ArcH::Single {
+                                vertex: placeholder,
+                                is_single_child: false,
+                            }),
+                            children: self.instantiate(&end)?,
+                            is_single_child: false,
+                        }
+                    }
+                    other => self.node(other)?,
+                };
+                Ok(ArcH::ArcH {
+                    vertex,
+                    fish,
+                    next: Box::new(next),
+                    is_single_child,
+                })
+            }
+            ArcH::ArcHWithNewLines {
+                prefix,
+                children,
+                is_single_child,
+            } => Ok(ArcH::ArcHWithNewLines {
+                prefix: Box::new(self.node(*prefix)?),
+                children: self.statements(children)?,
+                is_single_child,
+            }),
+            ArcH::Single { ref vertex, .. } => {
+                reject_call(vertex)?;
+                Ok(arch)
+            }
+            eval @ ArcH::EvalStatement { .. } => Ok(eval),
+        }
+    }
+
+    fn instantiate(&mut self, call: &Vertex) -> Result<Vec<ArcH>, TemplateError> {
+        let error = |message: String| TemplateError {
+            message,
+            span: call.span,
+        };
+        let (name, args) = call_of(call).expect("instantiate is only called on calls");
+        let template = self
+            .templates
+            .get(&name)
+            .ok_or_else(|| error(format!("unknown template `{}`", name)))?;
+        if args.len() != template.params.len() {
+            return Err(error(format!(
+                "template `{}` takes {} argument(s), got {}",
+                name,
+                template.params.len(),
+                args.len()
+            )));
+        }
+        if self.stack.contains(&name) {
+            return Err(error(format!("template `{}` instantiates itself", name)));
+        }
+
+        let bindings: Vec<(String, &str)> = template
+            .params
+            .iter()
+            .zip(&args)
+            .map(|(p, a)| (format!("${{{}}}", p), a.as_str()))
+            .collect();
+        let substitute = |text: &str| {
+            bindings
+                .iter()
+                .fold(text.to_string(), |t, (p, a)| t.replace(p.as_str(), a))
+        };
+        let mut body = template.body.clone();
+        for arch in &mut body {
+            arch.for_each_mut(
+                &mut |v| {
+                    let text = substitute(&v.segments.join("::"));
+                    // an argument may itself contain `::`, which only splits paths
+                    v.segments = if v.kind.is_path() {
+                        text.split("::").map(|s| s.trim().to_string()).collect()
+                    } else {
+                        vec![text]
+                    };
+                    attributes::map_strings(&mut v.attributes, &substitute);
+                },
+                &mut |f| {
+                    f.label = substitute(&f.label);
+                    attributes::map_strings(&mut f.attributes, &substitute);
+                },
+            );
+        }
+
+        self.stack.push(name);
+        let mut body = self.statements(body)?;
+        self.stack.pop();
+
+        // the outermost call is the one in the section, so it wins over nested calls
+        for arch in &mut body {
+            arch.for_each_mut(&mut |v| v.call_site = call.span, &mut |f| {
+                f.call_site = call.span
+            });
+        }
+        Ok(body)
+    }
+}
+
+fn reject_call(vertex: &Vertex) -> Result<(), TemplateError> {
+    match call_of(vertex) {
+        Some((name, _)) => Err(TemplateError {
+            message: format!(
+                "template `{}` can only be used as a statement, a `- ` child or at the end of a chain",
+                name
+            ),
+            span: vertex.span,
+        }),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{error, forms, written};
+
+    const CRUD: &str =
+        "[template Crud(x)]\nUI::${x}List\nUI::${x}Form ><submits> Logic::save${x}\n\n";
+
+    #[test]
+    fn a_call_ends_a_chain() {
+        let source = format!("{}[UI::App]\nUI::Screen ><renders> @Crud(Task)\n", CRUD);
+        assert_eq!(
+            written(&source),
+            [
+                "UI::App ><> UI::Screen ><renders> UI::TaskList",
+                "UI::App ><> UI::Screen ><renders> UI::TaskForm ><submits> Logic::saveTask",
+            ]
+        );
+    }
+
+    #[test]
+    fn a_call_as_a_statement_or_a_child() {
+        let source = format!("{}[UI::App]\n@Crud(A::B)\nUI::Root\n  - @Crud(C)\n", CRUD);
+        assert_eq!(
+            written(&source),
+            [
+                "UI::App ><> UI::A::BList",
+                "UI::App ><> UI::A::BForm ><submits> Logic::saveA::B",
+                "UI::App ><> UI::Root ><> UI::CList",
+                "UI::App ><> UI::Root ><> UI::CForm ><submits> Logic::saveC",
+            ]
+        );
+    }
+
+    #[test]
+    fn pasted_vertices_point_into_the_template_and_at_the_call() {
+        let source = format!("{}[UI::App]\n><renders> @Crud(Task)\n", CRUD);
+        let forms = forms(&source);
+        let list = &forms[0].last_point;
+        assert_eq!(list.name(), "UI::TaskList");
+        assert_eq!(list.span.unwrap().line_col(&source), (2, 1));
+        assert_eq!(list.call_site.unwrap().line_col(&source), (6, 12));
+        let (_, submits) = &forms[1].vf_pairs[1];
+        assert_eq!(submits.call_site.unwrap().line_col(&source), (6, 12));
+        // the section header was written in the section
+        assert_eq!(forms[0].vf_pairs[0].0.call_site, None);
+    }
+
+    #[test]
+    fn unknown_templates() {
+        // with no template defined at all
+        assert_eq!(
+            error("[UI::App]\n><renders> @Nope(B)\n"),
+            ("unknown template `Nope`".to_string(), Some((2, 12)))
+        );
+        let source = format!("{}[UI::App]\n@Nope(B)\n", CRUD);
+        assert_eq!(error(&source).0, "unknown template `Nope`");
+    }
+
+    #[test]
+    fn wrong_number_of_arguments() {
+        let source = format!("{}[UI::App]\n@Crud(A, B)\n", CRUD);
+        assert_eq!(
+            error(&source),
+            (
+                "template `Crud` takes 1 argument(s), got 2".to_string(),
+                Some((6, 1))
+            )
+        );
+    }
+
+    #[test]
+    fn templates_cannot_instantiate_themselves() {
+        let source = "[template A(x)]\n@B(${x})\n\n[template B(x)]\n@A(${x})\n\n[UI::App]\n@A(y)\n";
+        assert_eq!(error(source).0, "template `A` instantiates itself");
+    }
+
+    #[test]
+    fn calls_in_the_middle_of_a_chain() {
+        let source = format!("{}[UI::App]\n@Crud(A) ><x> B\n", CRUD);
+        assert_eq!(
+            error(&source).0,
+            "template `Crud` can only be used as a statement, a `- ` child or at the end of a chain"
+        );
+    }
+}
@@ -0,0 +1,43 @@
+// Helpers shared by the unit tests: parse a model the way the command line does
+use crate::arch::OriginalArcHForm;
+
+// a small model most tests can ask questions about
+pub const MODEL: &str = "[UI::App]\n\
+                         ><renders> UI::List ><calls> Logic::load ><reads> DB::Tasks\n\
+                         ><renders> UI::Form{owner=\"forms\"} ><submits> Logic::save \
+                         ><writes{async}> DB::Tasks\n";
+
+// the forms of `source`, section by section
+pub fn sections(source: &str) -> Vec<(&str, Vec<OriginalArcHForm>)> {
+    let sections = crate::parse_inputs_with(source, &crate::DEFAULT_OPTIONS).unwrap();
+    crate::collect_inputs(source, sections)
+}
+
+// every flattened form of `source`
+pub fn forms(source: &str) -> Vec<OriginalArcHForm> {
+    sections(source)
+        .into_iter()
+        .flat_map(|(_, forms)| forms)
+        .collect()
+}
+
+// every flattened form of `source`, written back in lo.fish syntax
+pub fn written(source: &str) -> Vec<String> {
+    forms(source)
+        .iter()
+        .map(|form| {
+            let mut s = String::new();
+            for (v, f) in &form.vf_pairs {
+                s.push_str(&format!("{} {} ", v.name(), f.operator()));
+            }
+            s + &form.last_point.name()
+        })
+        .collect()
+}
+
+// the message of the error `source` fails with, and the line and column it points at
+pub fn error(source: &str) -> (String, Option<(usize, usize)>) {
+    let e = crate::parse_inputs_with(source, &crate::DEFAULT_OPTIONS).unwrap_err();
+    let d = e.to_diagnostic();
+    (d.message, d.span.map(|span| span.line_col(source)))
+}
@@ -0,0 +1,409 @@
+use crate::arch::{Fish, OriginalArcHForm, Span, Vertex};
+use crate::diagnostics::{Diagnostic, Severity};
+use regex::Regex;
+use std::collections::{BTreeMap, BTreeSet};
+
+// Checks run over the flattened model (the output of convertToOriginalForm)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Rule {
+    // a vertex that is written only once in the whole file, usually a typo
+    UnknownVertex,
+    // two vertices joined without a fish, e.g. a section body line that starts with a vertex
+    EmptyFish,
+    // a fish pointing from a vertex to itself
+    SelfLoop,
+    // the same edge written more than once
+    DuplicateEdge,
+    // fish labels that only differ in casing, e.g. renders and Renders
+    FishCase,
+    // a vertex with an empty segment, e.g. a:: ::b
+    EmptySegment,
+    // a fish with nothing on one side, e.g. a trailing ><renders> or an empty $( )
+    EmptyVertex,
+    // style: fish labels must match a pattern (lowercase words by default)
+    FishLabelPattern,
+    // style: section headers and top-level statements must start with a namespaced vertex
+    TopLevelNamespace,
+    // an edge whose fish is declared in the [schema] section but between the wrong kinds
+    SchemaViolation,
+    // a fish that the [schema] section does not mention at all
+    UndeclaredFish,
+}
+
+impl Rule {
+    pub const ALL: [Rule; 11] = [
+        Rule::UnknownVertex,
+        Rule::EmptyFish,
+        Rule::SelfLoop,
+        Rule::DuplicateEdge,
+        Rule::FishCase,
+        Rule::EmptySegment,
+        Rule::EmptyVertex,
+        Rule::FishLabelPattern,
+        Rule::TopLevelNamespace,
+        Rule::SchemaViolation,
+        Rule::UndeclaredFish,
+    ];
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Rule::UnknownVertex => "unknown-vertex",
+            Rule::EmptyFish => "empty-fish",
+            Rule::SelfLoop => "self-loop",
+            Rule::DuplicateEdge => "duplicate-edge",
+            Rule::FishCase => "fish-case",
+            Rule::EmptySegment => "empty-segment",
+            Rule::EmptyVertex => "empty-vertex",
+            Rule::FishLabelPattern => "fish-label-pattern",
+            Rule::TopLevelNamespace => "top-level-namespace",
+            Rule::SchemaViolation => "schema-violation",
+            Rule::UndeclaredFish => "undeclared-fish",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Rule::UnknownVertex => "Vertex is only mentioned once, which usually means a typo",
+            Rule::EmptyFish => "Two vertices are joined without a fish",
+            Rule::SelfLoop => "Fish points from a vertex to itself",
+            Rule::DuplicateEdge => "Edge is declared more than once",
+            Rule::FishCase => "Fish label differs from another one only in casing",
+            Rule::EmptySegment => "Vertex has an empty segment",
+            Rule::EmptyVertex => "Fish has no vertex on one side",
+            Rule::FishLabelPattern => "Fish label does not match the configured pattern",
+            Rule::TopLevelNamespace => "Top-level vertex is not namespaced",
+            Rule::SchemaViolation => "Edge does not fit the relations declared in the schema",
+            Rule::UndeclaredFish => "Fish is not declared in the schema",
+        }
+    }
+
+    pub fn from_code(code: &str) -> Option<Rule> {
+        Rule::ALL.iter().copied().find(|r| r.code() == code)
+    }
+
+    // style rules are team conventions, so they are off unless configured
+    pub fn default_severity(&self) -> Option<Severity> {
+        match self {
+            Rule::UnknownVertex => Some(Severity::Info),
+            Rule::SchemaViolation => Some(Severity::Error),
+            Rule::FishLabelPattern | Rule::TopLevelNamespace | Rule::UndeclaredFish => None,
+            _ => Some(Severity::Warning),
+        }
+    }
+}
+
+// Which rules run, and how loud they are. A rule mapped to None is disabled.
+#[derive(Debug, Clone)]
+pub struct RuleConfig {
+    severities: BTreeMap<Rule, Option<Severity>>,
+    // used by fish-label-pattern
+    pub fish_label_pattern: Regex,
+    // used by top-level-namespace: how many segments a top-level vertex needs
+    pub namespace_depth: usize,
+}
+
+impl Default for RuleConfig {
+    fn default() -> Self {
+        RuleConfig {
+            severities: Rule::ALL
+                .iter()
+                .map(|r| (*r, r.default_severity()))
+                .collect(),
+            fish_label_pattern: Regex::new("^[a-z][a-z0-9_]*$").unwrap(),
+            namespace_depth: 2,
+        }
+    }
+}
+
+impl RuleConfig {
+    pub fn set(&mut self, rule: Rule, severity: Option<Severity>) {
+        self.severities.insert(rule, severity);
+    }
+
+    pub fn severity(&self, rule: Rule) -> Option<Severity> {
+        self.severities.get(&rule).copied().flatten()
+    }
+
+    // parse a `rule=severity` override such as `self-loop=error` or `unknown-vertex=off`
+    pub fn apply_override(&mut self, spec: &str) -> Result<(), String> {
+        let (code, level) = spec
+            .split_once('=')
+            .ok_or_else(|| format!("expected RULE=SEVERITY, got `{}`", spec))?;
+        let rule = Rule::from_code(code.trim())
+            .ok_or_else(|| format!("unknown rule `{}`", code.trim()))?;
+        self.set(rule, parse_level(level.trim())?);
+        Ok(())
+    }
+}
+
+// a severity name, or `off` to disable the rule
+pub fn parse_level(level: &str) -> Result<Option<Severity>, String> {
+    match level {
+        "off" | "allow" => Ok(None),
+        other => Severity::from_name(other)
+            .map(Some)
+            .ok_or_else(|| format!("unknown severity `{}`", other)),
+    }
+}
+
+// (from, fish, to) compared by text only
+type EdgeKey = (Vec<String>, String, Vec<String>);
+
+// Where something was written, and for pasted template statements the call that pasted
+// it: every call of a template shares the template's spans
+type Site = (Option<Span>, Option<Span>);
+
+// where to point at for an edge: the fish if it was written, otherwise its target
+fn edge_site(from: &Vertex, fish: &Fish, to: &Vertex) -> Site {
+    (
+        fish.span.or(to.span).or(from.span),
+        fish.call_site.or(to.call_site).or(from.call_site),
+    )
+}
+
+// Run every enabled rule over the flattened forms of each section. Diagnostics come back
+// sorted by position.
+pub fn validate(
+    sections: &[(&str, Vec<OriginalArcHForm>)],
+    config: &RuleConfig,
+) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let mut emit = |rule: Rule, message: String, span: Option<Span>| {
+        if let Some(severity) = config.severity(rule) {
+            diagnostics.push(Diagnostic::new(severity, rule.code(), message, span));
+        }
+    };
+
+    // the same source edge shows up in several forms once a prefix is shared,
+    // so everything below is keyed by where things were written
+    let mut vertex_sites: BTreeMap<Vec<String>, BTreeSet<Site>> = BTreeMap::new();
+    // the edges each vertex is part of, and where: a section header is written once but
+    // used by every statement in its section
+    let mut vertex_edges: BTreeMap<Vec<String>, BTreeSet<(EdgeKey, Site)>> = BTreeMap::new();
+    let mut edge_sites: BTreeMap<EdgeKey, BTreeSet<Site>> = BTreeMap::new();
+    let mut fish_spellings: BTreeMap<String, BTreeMap<Option<Span>, String>> = BTreeMap::new();
+
+    let forms = sections
+        .iter()
+        .flat_map(|(name, forms)| forms.iter().map(move |form| (!name.is_empty(), form)));
+    for (in_section, form) in forms {
+        // EVAL statements carry no vertex at all
+        if form.is_eval() {
+            continue;
+        }
+        let mut vertices: Vec<&Vertex> = form.vf_pairs.iter().map(|(v, _)| v).collect();
+        vertices.push(&form.last_point);
+        for v in vertices {
+            if v.is_placeholder() {
+                continue;
+            }
+            vertex_sites
+                .entry(v.segments.clone())
+                .or_default()
+                .insert((v.span, v.call_site));
+            if v.segments.len() > 1 && v.segments.iter().any(|s| s.is_empty()) {
+                emit(
+                    Rule::EmptySegment,
+                    format!("`{}` has an empty segment", v.name()),
+                    v.span,
+                );
+            }
+        }
+
+        for (i, (from, fish, to)) in form.edges().into_iter().enumerate() {
+            let site = edge_site(from, fish, to);
+            let span = site.0;
+            let key: EdgeKey = (
+                from.segments.clone(),
+                fish.label.clone(),
+                to.segments.clone(),
+            );
+            if from.is_placeholder() || to.is_placeholder() {
+                emit(
+                    Rule::EmptyVertex,
+                    format!("`{}` is missing a vertex on one side", fish.label),
+                    span,
+                );
+                continue;
+            }
+            for v in [from, to] {
+                vertex_edges
+                    .entry(v.segments.clone())
+                    .or_default()
+                    .insert((key.clone(), site));
+            }
+            // every form of a [section] starts with its header, joined to the statement
+            // without a fish: that is the layout, not a missing fish
+            let joins_section = in_section && i == 0;
+            if fish.label.is_empty() {
+                if !joins_section {
+                    emit(
+                        Rule::EmptyFish,
+                        format!(
+                            "`{}` and `{}` are joined without a fish",
+                            from.name(),
+                            to.name()
+                        ),
+                        span,
+                    );
+                }
+                // fish-less joins are not edges of the model, so they are not duplicates either
+                continue;
+            }
+            if !config.fish_label_pattern.is_match(&fish.label) {
+                emit(
+                    Rule::FishLabelPattern,
+                    format!(
+                        "`{}` does not match `{}`",
+                        fish.label,
+                        config.fish_label_pattern.as_str()
+                    ),
+                    fish.span,
+                );
+            }
+            fish_spellings
+                .entry(fish.label.to_lowercase())
+                .or_default()
+                .insert(fish.span, fish.label.clone());
+            if from == to {
+                emit(
+                    Rule::SelfLoop,
+                    format!("`{}` points at itself via `{}`", from.name(), fish.label),
+                    span,
+                );
+            }
+            edge_sites.entry(key).or_default().insert(site);
+        }
+    }
+
+    for (segments, sites) in vertex_sites.iter() {
+        let edges = vertex_edges.get(segments).map_or(0, |e| e.len());
+        if sites.len() == 1 && edges <= 1 {
+            emit(
+                Rule::UnknownVertex,
+                format!("`{}` is only mentioned once", segments.join("::")),
+                sites.iter().next().unwrap().0,
+            );
+        }
+    }
+
+    for ((from, fish, to), sites) in edge_sites.iter() {
+        // the first site is the original, every later one is a duplicate
+        for (span, _) in sites.iter().skip(1) {
+            emit(
+                Rule::DuplicateEdge,
+                format!(
+                    "`{} ><{}> {}` is already declared",
+                    from.join("::"),
+                    fish,
+                    to.join("::")
+                ),
+                *span,
+            );
+        }
+    }
+
+    for spellings in fish_spellings.values() {
+        let first = spellings.values().next().unwrap();
+        for (span, label) in spellings.iter() {
+            if label != first {
+                emit(
+                    Rule::FishCase,
+                    format!("`{}` is spelled `{}` elsewhere", label, first),
+                    *span,
+                );
+            }
+        }
+    }
+
+    diagnostics.sort_by(|a, b| (a.span, &a.code, &a.message).cmp(&(b.span, &b.code, &b.message)));
+    diagnostics.dedup();
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the codes and messages of the default rules on `source`
+    fn check(source: &str) -> Vec<(String, String)> {
+        validate(&crate::testing::sections(source), &RuleConfig::default())
+            .into_iter()
+            .map(|d| (d.code, d.message))
+            .collect()
+    }
+
+    fn codes(source: &str) -> Vec<String> {
+        check(source).into_iter().map(|(code, _)| code).collect()
+    }
+
+    #[test]
+    fn section_joins_are_not_duplicates_or_empty_fish() {
+        let source = "[UI::App]\nUI::List ><renders> UI::Item\nUI::List ><calls> Logic::load\n";
+        let found = codes(source);
+        assert!(
+            !found.contains(&"duplicate-edge".to_string()),
+            "{:?}",
+            found
+        );
+        assert!(!found.contains(&"empty-fish".to_string()), "{:?}", found);
+    }
+
+    #[test]
+    fn empty_fish_outside_a_section_join() {
+        let found = check("[UI::App]\nA ><x> B\n  - C\n");
+        assert!(found.contains(&(
+            "empty-fish".to_string(),
+            "`B` and `C` are joined without a fish".to_string()
+        )));
+    }
+
+    #[test]
+    fn every_call_of_a_template_is_a_site_of_its_own() {
+        let source = "[template T(x)]\n${x} ><uses> DB::Tasks\n\n[UI::App]\n@T(B)\n@T(B)\n";
+        let found = check(source);
+        assert!(found.contains(&(
+            "duplicate-edge".to_string(),
+            "`B ><uses> DB::Tasks` is already declared".to_string()
+        )));
+        // the header is joined to both calls
+        assert!(
+            !found.contains(&(
+                "unknown-vertex".to_string(),
+                "`UI::App` is only mentioned once".to_string()
+            )),
+            "{:?}",
+            found
+        );
+    }
+
+    #[test]
+    fn duplicate_edges_are_reported_once_per_repeat() {
+        let found = codes("A ><x> B\nA ><x> B\nA ><x> B\n");
+        assert_eq!(found.iter().filter(|c| *c == "duplicate-edge").count(), 2);
+    }
+
+    #[test]
+    fn a_header_used_by_many_statements_is_not_unknown() {
+        let found = check("[UI::App]\n><renders> UI::List\n><renders> UI::List\n");
+        let unknown: Vec<&str> = found
+            .iter()
+            .filter(|(code, _)| code == "unknown-vertex")
+            .map(|(_, message)| message.as_str())
+            .collect();
+        assert_eq!(unknown, Vec::<&str>::new());
+    }
+
+    #[test]
+    fn a_vertex_written_once_is_unknown() {
+        let found = check("[UI::App]\n><renders> UI::List\n><renders> UI::Lsit\n");
+        assert!(found.contains(&(
+            "unknown-vertex".to_string(),
+            "`UI::Lsit` is only mentioned once".to_string()
+        )));
+        assert!(found.contains(&(
+            "unknown-vertex".to_string(),
+            "`UI::List` is only mentioned once".to_string()
+        )));
+    }
+}
@@ -0,0 +1,68 @@
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+// Variables: `let NAME = value` (or `define NAME = value`) on a line of its own, used as
+// `${NAME}` in vertices and fish labels:
+//
+//   let BILLING = Company::Platform::Billing
+//   ${BILLING}::Invoices ><reads> ${BILLING}::Customers
+//
+// Definitions before the first [section] are visible everywhere, the others only in the
+// rest of their section. `--define NAME=VALUE` overrides both.
+pub type Scope = BTreeMap<String, String>;
+
+fn is_name(s: &str) -> bool {
+    let mut chars = s.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// `let NAME = value` -> (NAME, value); anything else is an ordinary statement
+pub fn parse_definition(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim();
+    let rest = line
+        .strip_prefix("let ")
+        .or_else(|| line.strip_prefix("define "))?;
+    let (name, value) = rest.split_once('=')?;
+    let name = name.trim();
+    if !is_name(name) {
+        return None;
+    }
+    Some((name, value.trim()))
+}
+
+// `NAME=VALUE`, as given to --define
+pub fn parse_override(spec: &str) -> Result<(String, String), String> {
+    match spec.split_once('=') {
+        Some((name, value)) if is_name(name.trim()) => {
+            Ok((name.trim().to_string(), value.trim().to_string()))
+        }
+        _ => Err(format!("--define expects NAME=VALUE, got `{}`", spec)),
+    }
+}
+
+// Replace every `${NAME}` in `text`; an undefined name is returned with its position in `text`
+pub fn interpolate(text: &str, scope: &Scope) -> Result<String, (String, Range<usize>)> {
+    let mut out = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => break,
+        };
+        let name = &rest[start + 2..end];
+        match scope.get(name) {
+            Some(value) => {
+                out.push_str(&rest[..start]);
+                out.push_str(value);
+            }
+            None => {
+                let offset = text.len() - rest.len();
+                return Err((name.to_string(), offset + start..offset + end + 1));
+            }
+        }
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
@@ -0,0 +1,245 @@
+use crate::arch::{ArcH, OriginalArcHForm};
+use crate::diagnostics::{Diagnostic, Severity};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use wasmi::{Config, Engine, Linker, Module, Store, StoreLimits, StoreLimitsBuilder, TypedFunc};
+
+// Sandboxed plugins compiled to WebAssembly (.wasm, or .wat text).
+//
+// ABI version 1. A plugin module
+//   - exports its linear memory as `memory`
+//   - exports `alloc(len: i32) -> i32`, returning where the host may write `len` bytes
+//   - exports `transform_arches` and/or `transform_forms`, `(ptr: i32, len: i32) -> i64`,
+//     which read the request at ptr..ptr+len and return the response as
+//     `(response_ptr << 32) | response_len`
+//   - imports nothing; the host provides no functions at all
+//
+// The request is UTF-8 JSON: { "abi": 1, "section": "UI::App", "arches": [...] } for
+// transform_arches, and the same with "forms" (see output.json) for transform_forms.
+// The response is UTF-8 JSON: { "arches" | "forms": [...], "diagnostics": [...] }, where
+// both keys are optional (a missing list leaves the section unchanged) and a diagnostic
+// is { "severity": "error" | "warning" | "info", "message": "...", "code": "..." }.
+//
+// Every call runs in a fresh instance, with at most `Limits::fuel` units of fuel
+// (roughly one per instruction) and `Limits::memory` bytes of linear memory.
+pub const ABI_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    pub fuel: u64,
+    pub memory: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            fuel: 100_000_000,
+            memory: 64 << 20,
+        }
+    }
+}
+
+pub struct WasmPlugin {
+    pub name: String,
+    engine: Engine,
+    module: Module,
+    limits: Limits,
+}
+
+#[derive(Deserialize)]
+struct Response<T> {
+    #[serde(alias = "arches", alias = "forms")]
+    items: Option<Vec<T>>,
+    #[serde(default)]
+    diagnostics: Vec<PluginDiagnostic>,
+}
+
+#[derive(Deserialize)]
+struct PluginDiagnostic {
+    severity: String,
+    message: String,
+    code: Option<String>,
+}
+
+struct State {
+    limits: StoreLimits,
+}
+
+impl WasmPlugin {
+    pub fn load(path: &str, limits: Limits) -> Result<WasmPlugin, String> {
+        let bytes = std::fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+        let bytes = if path.ends_with(".wat") {
+            wat::parse_bytes(&bytes)
+                .map_err(|e| format!("{}: {}", path, e))?
+                .into_owned()
+        } else {
+            bytes
+        };
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+        let module = Module::new(&engine, &bytes).map_err(|e| format!("{}: {}", path, e))?;
+
+        if module.imports().next().is_some() {
+            return Err(format!("{}: plugins may not import anything", path));
+        }
+        let exports: Vec<&str> = module.exports().map(|e| e.name()).collect();
+        for required in ["memory", "alloc"] {
+            if !exports.contains(&required) {
+                return Err(format!("{}: missing export `{}`", path, required));
+            }
+        }
+        if !exports.contains(&"transform_arches") && !exports.contains(&"transform_forms") {
+            return Err(format!(
+                "{}: exports neither `transform_arches` nor `transform_forms`",
+                path
+            ));
+        }
+
+        let name = std::path::Path::new(path)
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_else(|| path.to_string());
+        Ok(WasmPlugin {
+            name,
+            engine,
+            module,
+            limits,
+        })
+    }
+
+    fn exports(&self, function: &str) -> bool {
+        self.module.exports().any(|e| e.name() == function)
+    }
+
+    // hand `request` to `function` in a fresh instance and return its response
+    fn call(&self, function: &str, request: &[u8]) -> Result<Vec<u8>, String> {
+        let state = State {
+            limits: StoreLimitsBuilder::new()
+                .memory_size(self.limits.memory)
+                .trap_on_grow_failure(true)
+                .build(),
+        };
+        let mut store = Store::new(&self.engine, state);
+        store.limiter(|state| &mut state.limits);
+        store
+            .set_fuel(self.limits.fuel)
+            .map_err(|e| e.to_string())?;
+
+        let trap = |e: wasmi::Error| match e.as_trap_code() {
+            Some(wasmi::core::TrapCode::OutOfFuel) => {
+                format!("ran out of fuel (limit {})", self.limits.fuel)
+            }
+            Some(wasmi::core::TrapCode::GrowthOperationLimited) => {
+                format!("exceeded the memory limit of {} bytes", self.limits.memory)
+            }
+            _ => e.to_string(),
+        };
+        let instance = Linker::<State>::new(&self.engine)
+            .instantiate(&mut store, &self.module)
+            .and_then(|pre| pre.start(&mut store))
+            .map_err(trap)?;
+        let memory = instance
+            .get_memory(&store, "memory")
+            .ok_or("`memory` is not a memory")?;
+        let alloc: TypedFunc<i32, i32> = instance
+            .get_typed_func(&store, "alloc")
+            .map_err(|e| format!("`alloc`: {}", e))?;
+        let transform: TypedFunc<(i32, i32), i64> = instance
+            .get_typed_func(&store, function)
+            .map_err(|e| format!("`{}`: {}", function, e))?;
+
+        let len = request.len() as i32;
+        let ptr = alloc.call(&mut store, len).map_err(trap)?;
+        memory
+            .write(&mut store, ptr as u32 as usize, request)
+            .map_err(|_| "`alloc` returned memory out of bounds")?;
+        let packed = transform.call(&mut store, (ptr, len)).map_err(trap)? as u64;
+
+        let (start, len) = ((packed >> 32) as usize, (packed & 0xffff_ffff) as usize);
+        // the length comes from the plugin: check it against its memory before allocating
+        let out_of_bounds = || format!("`{}` returned a response out of bounds", function);
+        match start.checked_add(len) {
+            Some(end) if end <= memory.data(&store).len() => {}
+            _ => return Err(out_of_bounds()),
+        }
+        let mut response = vec![0; len];
+        memory
+            .read(&store, start, &mut response)
+            .map_err(|_| out_of_bounds())?;
+        Ok(response)
+    }
+
+    fn transform<T: Serialize + DeserializeOwned>(
+        &self,
+        function: &str,
+        key: &str,
+        section: &str,
+        items: Vec<T>,
+    ) -> Result<(Vec<T>, Vec<Diagnostic>), String> {
+        let request = json!({ "abi": ABI_VERSION, "section": section, key: items });
+        let response = self.call(function, request.to_string().as_bytes())?;
+        let response: Response<T> = serde_json::from_slice(&response)
+            .map_err(|e| format!("invalid response from `{}`: {}", function, e))?;
+
+        let mut diagnostics = Vec::new();
+        for d in response.diagnostics {
+            let severity = Severity::from_name(&d.severity)
+                .ok_or(format!("unknown severity `{}` in a diagnostic", d.severity))?;
+            let code = d.code.unwrap_or_else(|| self.name.clone());
+            diagnostics.push(Diagnostic::new(severity, &code, d.message, None));
+        }
+        Ok((response.items.unwrap_or(items), diagnostics))
+    }
+}
+
+// The plugins given on the command line, applied in order
+pub struct Host {
+    plugins: Vec<WasmPlugin>,
+}
+
+impl Host {
+    pub fn load(paths: &[String], limits: Limits) -> Result<Host, String> {
+        let plugins = paths
+            .iter()
+            .map(|path| WasmPlugin::load(path, limits))
+            .collect::<Result<_, _>>()?;
+        Ok(Host { plugins })
+    }
+
+    // a plugin that fails (traps, runs out of fuel, answers garbage) is reported as an error
+    fn apply<T: Serialize + DeserializeOwned + Clone>(
+        &self,
+        function: &str,
+        key: &str,
+        sections: &mut [(&str, Vec<T>)],
+    ) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+        for plugin in self.plugins.iter().filter(|p| p.exports(function)) {
+            for (name, items) in sections.iter_mut() {
+                match plugin.transform(function, key, name, items.clone()) {
+                    Ok((transformed, found)) => {
+                        *items = transformed;
+                        diagnostics.extend(found);
+                    }
+                    Err(message) => diagnostics.push(Diagnostic::new(
+                        Severity::Error,
+                        "plugin",
+                        format!("plugin {}: {}", plugin.name, message),
+                        None,
+                    )),
+                }
+            }
+        }
+        diagnostics
+    }
+
+    pub fn apply_arches(&self, sections: &mut [(&str, Vec<ArcH>)]) -> Vec<Diagnostic> {
+        self.apply("transform_arches", "arches", sections)
+    }
+
+    pub fn apply_forms(&self, sections: &mut [(&str, Vec<OriginalArcHForm>)]) -> Vec<Diagnostic> {
+        self.apply("transform_forms", "forms", sections)
+    }
+}
@@ -0,0 +1,29 @@
+use crate::arch::{ArcH, OriginalArcHForm};
+use crate::diagnostics::Diagnostic;
+
+// Stands in for wasm.rs when built without the `wasm-plugins` feature
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Limits {
+    pub fuel: u64,
+    pub memory: usize,
+}
+
+pub struct Host;
+
+impl Host {
+    pub fn load(paths: &[String], _limits: Limits) -> Result<Host, String> {
+        if paths.is_empty() {
+            Ok(Host)
+        } else {
+            Err("--plugin needs a build with the `wasm-plugins` feature".to_string())
+        }
+    }
+
+    pub fn apply_arches(&self, _sections: &mut [(&str, Vec<ArcH>)]) -> Vec<Diagnostic> {
+        Vec::new()
+    }
+
+    pub fn apply_forms(&self, _sections: &mut [(&str, Vec<OriginalArcHForm>)]) -> Vec<Diagnostic> {
+        Vec::new()
+    }
+}
@@ -0,0 +1,58 @@
+use notify_debouncer_mini::notify::RecursiveMode;
+use notify_debouncer_mini::{new_debouncer, DebounceEventResult};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::Duration;
+
+// `--watch`: run `rebuild` again whenever one of `files` changes, until interrupted.
+//
+// Editors often save by writing a new file and renaming it over the old one, which ends an
+// inotify watch on the file itself, so the directories are watched and events are filtered
+// by path. Changes closer together than DEBOUNCE (one save can be several events) cause a
+// single rebuild.
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+// the same path for a file given on the command line and for an event about it
+fn absolute(path: &Path) -> PathBuf {
+    let path = std::env::current_dir().unwrap_or_default().join(path);
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => std::fs::canonicalize(parent)
+            .unwrap_or_else(|_| parent.to_path_buf())
+            .join(name),
+        _ => path,
+    }
+}
+
+pub fn watch(files: &[String], mut rebuild: impl FnMut()) -> Result<(), String> {
+    let files: BTreeSet<PathBuf> = files.iter().map(|f| absolute(Path::new(f))).collect();
+    let directories: BTreeSet<&Path> = files.iter().filter_map(|f| f.parent()).collect();
+
+    let (sender, receiver) = mpsc::channel();
+    let mut debouncer = new_debouncer(DEBOUNCE, move |result: DebounceEventResult| {
+        let _ = sender.send(result);
+    })
+    .map_err(|e| e.to_string())?;
+    for directory in &directories {
+        debouncer
+            .watcher()
+            .watch(directory, RecursiveMode::NonRecursive)
+            .map_err(|e| format!("{}: {}", directory.display(), e))?;
+    }
+
+    eprintln!(
+        "watching {} file(s) for changes, press Ctrl-C to stop",
+        files.len()
+    );
+    for result in receiver {
+        match result {
+            Ok(events) => {
+                if events.iter().any(|e| files.contains(&absolute(&e.path))) {
+                    rebuild();
+                }
+            }
+            Err(e) => eprintln!("watch error: {}", e),
+        }
+    }
+    Ok(())
+}
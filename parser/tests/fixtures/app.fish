@@ -0,0 +1,12 @@
+## a small app, used by the integration tests
+[UI::App]
+><renders> UI::List
+
+><renders> UI::AddNewTask_Button
+
+[Logic::addTask]
+><writes{retries=3, async}> DB::Tasks{engine="sqlite", tags=[core, 1]}
+
+UI::Header ><renders> UI::Logo
+
+EVAL: count(UI::App ><renders>)
@@ -0,0 +1,11 @@
+## placeholders, spelled out and left implicit
+[UI::App]
+_ ><renders> UI::List
+UI::List ><calls>
+  - Logic::load
+UI::Form ><submits> _
+
+[Logic::load]
+Logic::load
+  EVAL: 1 == 1
+  DB::Tasks
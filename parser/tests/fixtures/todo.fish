@@ -0,0 +1,2 @@
+[UI::App]
+UI::App ><renders> TODO
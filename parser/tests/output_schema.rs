@@ -0,0 +1,114 @@
+// Runs the parser binary on a fixture and checks output.json against the published schema
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+fn manifest_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+}
+
+// run rust-parser in a fresh directory, so output.json does not clash between tests
+fn parse_fixture(name: &str) -> serde_json::Value {
+    let dir = std::env::temp_dir().join(format!("lofish-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let fixture = manifest_dir().join("tests/fixtures").join(name);
+    let status = Command::new(env!("CARGO_BIN_EXE_rust-parser"))
+        .arg(&fixture)
+        .current_dir(&dir)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let output = std::fs::read_to_string(dir.join("output.json")).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    serde_json::from_str(&output).unwrap()
+}
+
+fn load_schema(path: &Path) -> serde_json::Value {
+    serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+}
+
+#[test]
+fn output_matches_schema() {
+    let schema = load_schema(&manifest_dir().join("schemas/output-v1.schema.json"));
+    let validator = jsonschema::validator_for(&schema).unwrap();
+    let output = parse_fixture("app.fish");
+
+    let errors: Vec<String> = validator
+        .iter_errors(&output)
+        .map(|e| format!("{} at {}", e, e.instance_path))
+        .collect();
+    assert!(errors.is_empty(), "{:#?}", errors);
+
+    assert_eq!(output["version"], 1);
+    let names: Vec<&str> = output["sections"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|s| s["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(names, ["UI::App", "Logic::addTask"]);
+    assert_eq!(
+        output["edges"][0],
+        serde_json::json!({
+            "from": { "segments": ["UI", "App"] },
+            "fish": { "label": "renders" },
+            "to": { "segments": ["UI", "List"] }
+        })
+    );
+
+    // ><writes{retries=3, async}> DB::Tasks{engine="sqlite", tags=[core, 1]}
+    let writes = &output["sections"][1]["forms"][0];
+    assert_eq!(
+        writes["vf_pairs"][0]["fish"],
+        serde_json::json!({ "label": "writes", "attributes": { "retries": 3, "async": true } })
+    );
+    assert_eq!(
+        writes["last_point"],
+        serde_json::json!({
+            "segments": ["DB", "Tasks"],
+            "attributes": { "engine": "sqlite", "tags": ["core", 1] }
+        })
+    );
+    // the edge carries the same attributes
+    assert_eq!(output["edges"][2]["fish"], writes["vf_pairs"][0]["fish"]);
+    assert_eq!(output["edges"][2]["to"], writes["last_point"]);
+}
+
+#[test]
+fn placeholders_match_schema() {
+    let schema = load_schema(&manifest_dir().join("schemas/output-v1.schema.json"));
+    let validator = jsonschema::validator_for(&schema).unwrap();
+    // an EVAL among the children used to make combining the forms panic
+    let output = parse_fixture("placeholders.fish");
+    assert!(validator.is_valid(&output));
+
+    let forms = &output["sections"][0]["forms"];
+    assert_eq!(
+        forms[0]["vf_pairs"][0]["vertex"]["segments"],
+        serde_json::json!(["UI", "App"])
+    );
+    assert_eq!(
+        forms[1]["last_point"],
+        serde_json::json!({ "segments": ["Logic", "load"] })
+    );
+    // a trailing fish with nothing after it keeps its placeholder
+    assert_eq!(
+        forms[2]["last_point"],
+        serde_json::json!({ "segments": [""], "kind": "placeholder" })
+    );
+    // but the edge to it is not one of the model's
+    let edges = output["edges"].as_array().unwrap();
+    assert!(edges
+        .iter()
+        .all(|e| e["from"].get("kind").is_none() && e["to"].get("kind").is_none()));
+
+    // the EVAL under `Logic::load` is a form of its own, and `DB::Tasks` still follows on
+    let forms = &output["sections"][1]["forms"];
+    assert_eq!(forms.as_array().unwrap().len(), 2);
+    assert_eq!(
+        forms[0]["last_point"],
+        serde_json::json!({ "segments": ["DB", "Tasks"] })
+    );
+    assert_eq!(forms[0]["vf_pairs"].as_array().unwrap().len(), 2);
+    assert_eq!(forms[1]["executable_expression"], "1 == 1");
+    assert!(forms[1]["vf_pairs"].as_array().unwrap().is_empty());
+}
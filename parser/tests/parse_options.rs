@@ -0,0 +1,146 @@
+// Runs the parser binary and its subcommands with --define and --flag, which every one of
+// them must parse the file with
+use std::process::Command;
+
+struct Run {
+    success: bool,
+    stdout: String,
+    stderr: String,
+}
+
+// run rust-parser with `args` in a fresh directory holding `source` as model.fish
+fn run(test: &str, source: &str, args: &[&str]) -> Run {
+    let dir = std::env::temp_dir().join(format!("lofish-options-{}-{}", test, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("model.fish"), source).unwrap();
+    let result = Command::new(env!("CARGO_BIN_EXE_rust-parser"))
+        .args(args)
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    Run {
+        success: result.status.success(),
+        stdout: String::from_utf8(result.stdout).unwrap(),
+        stderr: String::from_utf8(result.stderr).unwrap(),
+    }
+}
+
+// `T` is only defined on the command line
+const UNDEFINED: &str = "[UI::App]\n><renders> ${T}::List\n><renders> ${T}::List\n\
+                         EVAL: count(UI::App ><renders>)\n";
+
+#[test]
+fn validate_parses_with_the_defines() {
+    let run = run(
+        "validate",
+        UNDEFINED,
+        &["--define", "T=Svc", "--validate", "model.fish"],
+    );
+    assert!(run.success, "{}", run.stderr);
+    assert!(!run.stderr.contains("undefined variable"), "{}", run.stderr);
+    // the validation sees the same model as output.json
+    assert!(
+        run.stderr
+            .contains("`UI::App ><renders> Svc::List` is already declared"),
+        "{}",
+        run.stderr
+    );
+}
+
+#[test]
+fn subcommands_parse_with_the_defines() {
+    let lint = run(
+        "lint",
+        UNDEFINED,
+        &["lint", "--define", "T=Svc", "model.fish"],
+    );
+    assert!(
+        !lint.stdout.contains("undefined variable"),
+        "{}",
+        lint.stdout
+    );
+
+    let eval = run(
+        "eval",
+        UNDEFINED,
+        &["eval", "--define", "T=Svc", "model.fish"],
+    );
+    assert!(eval.success, "{}", eval.stderr);
+    assert!(eval.stdout.contains("=> 1"), "{}", eval.stdout);
+
+    let query = run(
+        "query",
+        UNDEFINED,
+        &["query", "--define", "T=Svc", "model.fish", "out", "UI::App"],
+    );
+    assert!(query.success, "{}", query.stderr);
+    assert!(query.stdout.contains("Svc::List"), "{}", query.stdout);
+
+    let diff = run(
+        "diff",
+        UNDEFINED,
+        &["diff", "--define", "T=Svc", "model.fish", "model.fish"],
+    );
+    assert!(diff.success, "{}", diff.stderr);
+    assert_eq!(diff.stdout, "");
+}
+
+#[test]
+fn without_the_define_the_variable_is_undefined() {
+    let run = run("undefined", UNDEFINED, &["eval", "model.fish"]);
+    assert!(!run.success);
+    assert!(
+        run.stderr.contains("undefined variable `T`"),
+        "{}",
+        run.stderr
+    );
+}
+
+// the self-loop only exists in the cache variant
+const FLAGGED: &str = "[Logic::Tasks]\n#if cache\nCache::Tasks ><refreshes> Cache::Tasks\n#endif\n\
+                       ><reads> DB::Tasks\n";
+
+#[test]
+fn validate_checks_the_flagged_model() {
+    let with = run(
+        "flagged",
+        FLAGGED,
+        &["--flag", "cache", "--validate", "model.fish"],
+    );
+    assert!(
+        with.stderr.contains("`Cache::Tasks` points at itself"),
+        "{}",
+        with.stderr
+    );
+    let without = run("unflagged", FLAGGED, &["--validate", "model.fish"]);
+    assert!(
+        !without.stderr.contains("points at itself"),
+        "{}",
+        without.stderr
+    );
+}
+
+#[test]
+fn subcommands_take_flags() {
+    let lint = run(
+        "lint-flag",
+        FLAGGED,
+        &["lint", "--flag", "cache", "model.fish"],
+    );
+    assert!(lint.stdout.contains("self-loop"), "{}", lint.stdout);
+
+    let query = run(
+        "query-flag",
+        FLAGGED,
+        &[
+            "query",
+            "--flag",
+            "cache",
+            "model.fish",
+            "vertices",
+            "Cache::*",
+        ],
+    );
+    assert!(query.stdout.contains("Cache::Tasks"), "{}", query.stdout);
+}
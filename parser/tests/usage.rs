@@ -0,0 +1,39 @@
+// Command-line mistakes print the usage to stderr and exit with status 2
+use std::process::Command;
+
+fn run(args: &[&str]) -> (Option<i32>, String) {
+    let result = Command::new(env!("CARGO_BIN_EXE_rust-parser"))
+        .args(args)
+        .output()
+        .unwrap();
+    (
+        result.status.code(),
+        String::from_utf8(result.stderr).unwrap(),
+    )
+}
+
+#[test]
+fn usage_errors_exit_with_2() {
+    for (args, usage) in [
+        (&[][..], "usage: rust-parser [--validate]"),
+        (
+            &["lint", "--format", "xml", "a.fish"][..],
+            "usage: rust-parser lint",
+        ),
+        (&["eval"][..], "usage: rust-parser eval"),
+        (&["query", "a.fish"][..], "usage: rust-parser query"),
+        (&["diff", "a.fish"][..], "usage: rust-parser diff"),
+    ] {
+        let (code, stderr) = run(args);
+        assert_eq!(code, Some(2), "{:?}: {}", args, stderr);
+        assert!(stderr.starts_with(usage), "{:?}: {}", args, stderr);
+        // printed as text, not as a debug string
+        assert!(!stderr.contains("\\n"), "{}", stderr);
+    }
+}
+
+#[test]
+fn query_usage_lists_the_queries() {
+    let (_, stderr) = run(&["query"]);
+    assert!(stderr.contains("\n  vertices PATTERN"), "{}", stderr);
+}
@@ -0,0 +1,190 @@
+// Runs the parser binary with WebAssembly plugins (see src/wasm.rs for the ABI)
+#![cfg(feature = "wasm-plugins")]
+use std::path::PathBuf;
+use std::process::Command;
+
+fn manifest_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+}
+
+struct Run {
+    success: bool,
+    stderr: String,
+    output: Option<serde_json::Value>,
+}
+
+// run rust-parser on a fixture in a fresh directory, with the given plugins written to it
+fn run(test: &str, fixture: &str, plugins: &[(&str, String)], extra: &[&str]) -> Run {
+    let dir = std::env::temp_dir().join(format!("lofish-wasm-{}-{}", test, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut command = Command::new(env!("CARGO_BIN_EXE_rust-parser"));
+    for (name, source) in plugins {
+        std::fs::write(dir.join(name), source).unwrap();
+        command.arg("--plugin").arg(dir.join(name));
+    }
+    let result = command
+        .args(extra)
+        .arg(manifest_dir().join("tests/fixtures").join(fixture))
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    let output = std::fs::read_to_string(dir.join("output.json"))
+        .ok()
+        .map(|text| serde_json::from_str(&text).unwrap());
+    std::fs::remove_dir_all(&dir).unwrap();
+    Run {
+        success: result.status.success(),
+        stderr: String::from_utf8(result.stderr).unwrap(),
+        output,
+    }
+}
+
+// a plugin whose `function` ignores its request and answers `response`
+fn constant_plugin(function: &str, response: &str) -> String {
+    format!(
+        r#"(module
+  (memory (export "memory") 1)
+  (data (i32.const 0) "{}")
+  (func (export "alloc") (param i32) (result i32) (i32.const 1024))
+  (func (export "{}") (param i32 i32) (result i64) (i64.const {})))"#,
+        response.replace('"', "\\\""),
+        function,
+        response.len()
+    )
+}
+
+fn sample_plugin() -> (&'static str, String) {
+    let path = manifest_dir().join("wasm/flag-todo.wat");
+    ("flag-todo.wat", std::fs::read_to_string(path).unwrap())
+}
+
+#[test]
+fn sample_plugin_warns_about_todo() {
+    let flagged = run("todo", "todo.fish", &[sample_plugin()], &[]);
+    assert!(flagged.success, "{}", flagged.stderr);
+    assert!(flagged
+        .stderr
+        .contains("warning[todo]: a vertex is still named TODO"));
+    assert!(flagged.output.is_some());
+
+    let clean = run("clean", "app.fish", &[sample_plugin()], &[]);
+    assert!(clean.success, "{}", clean.stderr);
+    assert_eq!(clean.stderr, "");
+}
+
+#[test]
+fn forms_can_be_replaced() {
+    let plugin = constant_plugin("transform_forms", r#"{"forms":[]}"#);
+    let result = run("forms", "app.fish", &[("empty.wat", plugin)], &[]);
+    assert!(result.success, "{}", result.stderr);
+    let output = result.output.unwrap();
+    assert_eq!(output["edges"], serde_json::json!([]));
+    assert_eq!(output["sections"][0]["name"], "UI::App");
+    assert_eq!(output["sections"][0]["forms"], serde_json::json!([]));
+}
+
+#[test]
+fn arches_can_be_replaced() {
+    let plugin = constant_plugin("transform_arches", r#"{"arches":[]}"#);
+    let result = run("arches", "app.fish", &[("empty.wat", plugin)], &[]);
+    assert!(result.success, "{}", result.stderr);
+    assert_eq!(result.output.unwrap()["edges"], serde_json::json!([]));
+}
+
+#[test]
+fn error_diagnostics_stop_the_parse() {
+    let plugin = constant_plugin(
+        "transform_forms",
+        r#"{"diagnostics":[{"severity":"error","code":"house-style","message":"no"}]}"#,
+    );
+    let result = run("error", "app.fish", &[("strict.wat", plugin)], &[]);
+    assert!(!result.success);
+    assert!(result.stderr.contains("error[house-style]: no"));
+    assert!(result.output.is_none());
+}
+
+#[test]
+fn fuel_is_limited() {
+    let plugin = r#"(module
+  (memory (export "memory") 1)
+  (func (export "alloc") (param i32) (result i32) (i32.const 1024))
+  (func (export "transform_forms") (param i32 i32) (result i64)
+    (loop $forever (br $forever))
+    (i64.const 0)))"#;
+    let result = run(
+        "fuel",
+        "app.fish",
+        &[("spin.wat", plugin.to_string())],
+        &["--plugin-fuel", "100000"],
+    );
+    assert!(!result.success);
+    assert!(
+        result
+            .stderr
+            .contains("plugin spin: ran out of fuel (limit 100000)"),
+        "{}",
+        result.stderr
+    );
+}
+
+#[test]
+fn memory_is_limited() {
+    let plugin = r#"(module
+  (memory (export "memory") 1)
+  (func (export "alloc") (param i32) (result i32)
+    (drop (memory.grow (i32.const 64)))
+    (i32.const 1024))
+  (func (export "transform_forms") (param i32 i32) (result i64) (i64.const 0)))"#;
+    let result = run(
+        "memory",
+        "app.fish",
+        &[("hungry.wat", plugin.to_string())],
+        &["--plugin-memory", "1"],
+    );
+    assert!(!result.success);
+    assert!(
+        result.stderr.contains("exceeded the memory limit"),
+        "{}",
+        result.stderr
+    );
+}
+
+#[test]
+fn responses_out_of_bounds_are_refused() {
+    // a response of 0xffff_ffff bytes, far more than the one page of memory
+    let plugin = r#"(module
+  (memory (export "memory") 1)
+  (func (export "alloc") (param i32) (result i32) (i32.const 1024))
+  (func (export "transform_forms") (param i32 i32) (result i64) (i64.const 0xffffffff)))"#;
+    let result = run(
+        "bounds",
+        "app.fish",
+        &[("liar.wat", plugin.to_string())],
+        &[],
+    );
+    assert!(!result.success);
+    assert!(
+        result
+            .stderr
+            .contains("plugin liar: `transform_forms` returned a response out of bounds"),
+        "{}",
+        result.stderr
+    );
+}
+
+#[test]
+fn imports_are_refused() {
+    let plugin = r#"(module
+  (import "env" "exit" (func))
+  (memory (export "memory") 1)
+  (func (export "alloc") (param i32) (result i32) (i32.const 1024))
+  (func (export "transform_forms") (param i32 i32) (result i64) (i64.const 0)))"#;
+    let result = run(
+        "imports",
+        "app.fish",
+        &[("escape.wat", plugin.to_string())],
+        &[],
+    );
+    assert!(!result.success);
+    assert!(result.stderr.contains("plugins may not import anything"));
+}
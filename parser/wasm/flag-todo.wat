@@ -0,0 +1,57 @@
+;; Sample lo.fish WebAssembly plugin (ABI 1, see src/wasm.rs).
+;;
+;; Warns when a section still has a vertex segment named TODO, e.g.
+;;   UI::App ><renders> TODO
+;;
+;;   rust-parser --plugin wasm/flag-todo.wat app.fish
+;;
+;; It leaves the forms alone, so its response only carries diagnostics.
+(module
+  (memory (export "memory") 1)
+
+  ;; responses
+  (data (i32.const 0) "{\"diagnostics\":[{\"severity\":\"warning\",\"code\":\"todo\",\"message\":\"a vertex is still named TODO\"}]}")
+  (data (i32.const 128) "{}")
+
+  ;; requests are written from here on, one after the other
+  (global $next (mut i32) (i32.const 1024))
+
+  (func (export "alloc") (param $len i32) (result i32)
+    (local $ptr i32)
+    (local.set $ptr (global.get $next))
+    ;; grow until ptr + len fits
+    (block $done
+      (loop $grow
+        (br_if $done
+          (i32.le_u
+            (i32.add (local.get $ptr) (local.get $len))
+            (i32.mul (memory.size) (i32.const 65536))))
+        (if (i32.eq (memory.grow (i32.const 1)) (i32.const -1))
+          (then unreachable))
+        (br $grow)))
+    (global.set $next (i32.add (local.get $ptr) (local.get $len)))
+    (local.get $ptr))
+
+  ;; is the JSON string "TODO" (with its quotes) at $at?
+  (func $todo_at (param $at i32) (result i32)
+    (i32.and
+      (i32.and
+        (i32.eq (i32.load8_u (local.get $at)) (i32.const 34))
+        (i32.eq (i32.load8_u offset=5 (local.get $at)) (i32.const 34)))
+      ;; "TODO" read as a little-endian i32
+      (i32.eq (i32.load offset=1 (local.get $at)) (i32.const 0x4f444f54))))
+
+  (func (export "transform_forms") (param $ptr i32) (param $len i32) (result i64)
+    (local $at i32)
+    (local $end i32)
+    (local.set $at (local.get $ptr))
+    (local.set $end (i32.sub (i32.add (local.get $ptr) (local.get $len)) (i32.const 5)))
+    (block $none
+      (loop $scan
+        (br_if $none (i32.ge_s (local.get $at) (local.get $end)))
+        (if (call $todo_at (local.get $at))
+          (then (return (i64.const 95))))
+        (local.set $at (i32.add (local.get $at) (i32.const 1)))
+        (br $scan)))
+    ;; (128 << 32) | 2
+    (i64.or (i64.shl (i64.const 128) (i64.const 32)) (i64.const 2))))